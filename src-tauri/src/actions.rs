@@ -0,0 +1,44 @@
+use std::fmt;
+
+use crate::config::ButtonAction;
+use crate::keyboard::{self, KeyboardError};
+use crate::variables;
+
+/// アクションの実行の失敗
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionError {
+    Keyboard(KeyboardError),
+    /// 変数を更新できない（宣言されていない、数値でないなど）
+    Variable(String),
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionError::Keyboard(e) => write!(f, "{}", e),
+            ActionError::Variable(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// ボタンのアクションを実行
+pub fn execute(action: &ButtonAction) -> Result<(), ActionError> {
+    match action {
+        ButtonAction::Shortcut { keys } => {
+            keyboard::execute_shortcut(keys).map_err(ActionError::Keyboard)
+        }
+        ButtonAction::TextAndEnter { text } => {
+            keyboard::execute_text_and_enter(&variables::interpolate(text))
+                .map_err(ActionError::Keyboard)
+        }
+        ButtonAction::SetVariable { name, value } => variables::set(name, value)
+            .map(|_| ())
+            .map_err(ActionError::Variable),
+        ButtonAction::IncrementVariable { name, by } => variables::increment(name, *by)
+            .map(|_| ())
+            .map_err(ActionError::Variable),
+        ButtonAction::ResetVariable { name } => variables::reset(name)
+            .map(|_| ())
+            .map_err(ActionError::Variable),
+    }
+}
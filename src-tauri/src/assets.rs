@@ -0,0 +1,117 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::AppConfig;
+
+/// アップロードできる画像の最大サイズ
+const MAX_ASSET_SIZE: usize = 1024 * 1024;
+
+/// アップロードできる画像の拡張子
+const ALLOWED_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "svg"];
+
+/// 組み込みアイコン（24x24、線の色はボタンの文字色に合わせる）
+const BUILTIN_ICONS: &[(&str, &str)] = &[
+    ("play", r#"<path d="M7 4l13 8-13 8z"/>"#),
+    ("pause", r#"<path d="M7 4v16M17 4v16"/>"#),
+    ("stop", r#"<rect x="5" y="5" width="14" height="14" rx="1"/>"#),
+    ("next", r#"<path d="M5 4l11 8-11 8zM19 4v16"/>"#),
+    ("previous", r#"<path d="M19 4L8 12l11 8zM5 4v16"/>"#),
+    ("record", r#"<circle cx="12" cy="12" r="7"/>"#),
+    ("mic", r#"<rect x="9" y="3" width="6" height="11" rx="3"/><path d="M5 11a7 7 0 0 0 14 0M12 18v3"/>"#),
+    ("volume-up", r#"<path d="M4 9h4l5-4v14l-5-4H4zM16 9a4 4 0 0 1 0 6M19 6a8 8 0 0 1 0 12"/>"#),
+    ("volume-down", r#"<path d="M4 9h4l5-4v14l-5-4H4zM16 9a4 4 0 0 1 0 6"/>"#),
+    ("mute", r#"<path d="M4 9h4l5-4v14l-5-4H4zM16 9l5 6M21 9l-5 6"/>"#),
+    ("arrow-up", r#"<path d="M12 20V4M5 11l7-7 7 7"/>"#),
+    ("arrow-down", r#"<path d="M12 4v16M5 13l7 7 7-7"/>"#),
+    ("arrow-left", r#"<path d="M20 12H4M11 5l-7 7 7 7"/>"#),
+    ("arrow-right", r#"<path d="M4 12h16M13 5l7 7-7 7"/>"#),
+    ("enter", r#"<path d="M20 5v7H5M10 7l-5 5 5 5"/>"#),
+    ("backspace", r#"<path d="M8 5h12v14H8l-5-7zM11 9l6 6M17 9l-6 6"/>"#),
+    ("check", r#"<path d="M4 12l5 5L20 6"/>"#),
+    ("close", r#"<path d="M5 5l14 14M19 5L5 19"/>"#),
+    ("refresh", r#"<path d="M20 12a8 8 0 1 1-3-6.2M20 4v5h-5"/>"#),
+    ("camera", r#"<path d="M3 7h4l2-3h6l2 3h4v13H3z"/><circle cx="12" cy="13" r="4"/>"#),
+];
+
+fn assets_dir() -> PathBuf {
+    AppConfig::config_dir().join("assets")
+}
+
+/// 内容のハッシュから保存名を決める（同じ画像は同じ名前になる）
+pub fn asset_name(data: &[u8], extension: &str) -> String {
+    let hash = Sha256::digest(data);
+    let hex: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.{}", hex, extension.to_lowercase())
+}
+
+/// 保存名として正しいか（パス区切りなどを含まない「ハッシュ.拡張子」）
+fn is_valid_name(name: &str) -> bool {
+    match name.split_once('.') {
+        Some((hash, extension)) => {
+            hash.len() == 32
+                && hash.chars().all(|c| c.is_ascii_hexdigit())
+                && ALLOWED_EXTENSIONS.contains(&extension)
+        }
+        None => false,
+    }
+}
+
+/// 画像を assets/ に保存し、保存名を返す
+pub fn store_asset(file_name: &str, data: &[u8]) -> Result<String, String> {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .filter(|ext| ALLOWED_EXTENSIONS.contains(&ext.as_str()))
+        .ok_or_else(|| format!("Unsupported image type: {}", file_name))?;
+    if data.len() > MAX_ASSET_SIZE {
+        return Err(format!(
+            "Image is too large ({} bytes, max {})",
+            data.len(),
+            MAX_ASSET_SIZE
+        ));
+    }
+
+    let name = asset_name(data, &extension);
+    let dir = assets_dir();
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let path = dir.join(&name);
+    if !path.exists() {
+        fs::write(&path, data).map_err(|e| e.to_string())?;
+    }
+    Ok(name)
+}
+
+/// 保存済みの画像を読み込む
+pub fn read_asset(name: &str) -> Option<Vec<u8>> {
+    if !is_valid_name(name) {
+        return None;
+    }
+    fs::read(assets_dir().join(name)).ok()
+}
+
+/// 保存名からContent-Typeを決める
+pub fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 組み込みアイコンの名前一覧
+pub fn builtin_icon_names() -> Vec<&'static str> {
+    BUILTIN_ICONS.iter().map(|(name, _)| *name).collect()
+}
+
+/// 組み込みアイコンのSVG
+pub fn builtin_icon_svg(name: &str) -> Option<String> {
+    let (_, body) = BUILTIN_ICONS.iter().find(|(n, _)| *n == name)?;
+    Some(format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">{}</svg>"#,
+        body
+    ))
+}
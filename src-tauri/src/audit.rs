@@ -0,0 +1,329 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::AppConfig;
+
+/// 操作履歴のファイル（設定ファイルと同じディレクトリ、1行に1件のJSON）
+const LOG_FILE_NAME: &str = "audit.log";
+
+/// このサイズを超えたら `audit.log.1` に回して新しいファイルに書く
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// 残す古いファイルの数（`audit.log.1` 〜 `audit.log.3`）
+const MAX_ROTATED: usize = 3;
+
+/// 検索で返す件数（指定がない場合と上限）
+const DEFAULT_LIMIT: usize = 200;
+const MAX_LIMIT: usize = 1000;
+
+/// アクションを受け付けた経路
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Http,
+    WebSocket,
+}
+
+/// 操作履歴の1件（実行できなかった試行も残す）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// 日時（UNIX時間のミリ秒）
+    pub timestamp: u64,
+    /// ペアリングした端末の名前（PINで接続した場合は `None`）
+    pub device: Option<String>,
+    /// 認証したユーザー名（全体のPINや端末なら `None`）
+    pub user: Option<String>,
+    /// セッションIDの先頭（同じPINで接続した複数のスマホを見分ける）
+    pub session: String,
+    pub ip: String,
+    pub button_id: String,
+    pub transport: Transport,
+    pub success: bool,
+    /// 失敗した場合のエラーコードとメッセージ（APIのエラーと同じ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 受け付けてから実行し終わるまでの時間（ミリ秒）
+    pub latency_ms: u64,
+}
+
+/// 操作履歴の検索条件（指定しない項目は絞り込まない）
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    /// 端末名・ユーザー名・セッションIDの一部（大文字小文字を区別しない）
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub button_id: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub success: Option<bool>,
+    /// この日時以降（UNIX時間のミリ秒）
+    #[serde(default)]
+    pub since: Option<u64>,
+    /// この日時より前（UNIX時間のミリ秒）
+    #[serde(default)]
+    pub until: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        let device_matches = self.device.as_deref().is_none_or(|device| {
+            let device = device.to_lowercase();
+            [
+                entry.device.as_deref(),
+                entry.user.as_deref(),
+                Some(&entry.session),
+            ]
+            .into_iter()
+            .flatten()
+            .any(|name| name.to_lowercase().contains(&device))
+        });
+        device_matches
+            && self
+                .button_id
+                .as_deref()
+                .is_none_or(|id| entry.button_id == id)
+            && self.ip.as_deref().is_none_or(|ip| entry.ip == ip)
+            && self.success.is_none_or(|success| entry.success == success)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+    }
+}
+
+/// 書き込みとファイルの切り替えが重ならないようにする
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+type RecordListener = Box<dyn Fn(&AuditEntry) + Send + Sync>;
+
+/// 記録したときに呼ぶ処理（設定画面への通知）
+static RECORD_LISTENER: once_cell::sync::OnceCell<RecordListener> =
+    once_cell::sync::OnceCell::new();
+
+/// 記録したときの処理を登録
+pub fn on_record<F: Fn(&AuditEntry) + Send + Sync + 'static>(listener: F) {
+    let _ = RECORD_LISTENER.set(Box::new(listener));
+}
+
+fn log_path(generation: usize) -> PathBuf {
+    let dir = AppConfig::config_dir();
+    match generation {
+        0 => dir.join(LOG_FILE_NAME),
+        n => dir.join(format!("{}.{}", LOG_FILE_NAME, n)),
+    }
+}
+
+/// 現在の日時（UNIX時間のミリ秒）
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// 大きくなったファイルを古いファイルに回す（一番古いものは消える）
+fn rotate_if_needed() -> std::io::Result<()> {
+    let current = log_path(0);
+    if fs::metadata(&current).map_or(true, |meta| meta.len() < MAX_LOG_BYTES) {
+        return Ok(());
+    }
+    for generation in (1..MAX_ROTATED).rev() {
+        let from = log_path(generation);
+        if from.exists() {
+            fs::rename(&from, log_path(generation + 1))?;
+        }
+    }
+    fs::rename(&current, log_path(1))
+}
+
+fn append(entry: &AuditEntry) -> Result<(), String> {
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    let _lock = WRITE_LOCK.lock().unwrap();
+    rotate_if_needed().map_err(|e| e.to_string())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path(0))
+        .map_err(|e| e.to_string())?;
+    writeln!(file, "{}", line).map_err(|e| e.to_string())
+}
+
+/// アクションの試行を記録して設定画面に知らせる
+pub fn record(entry: AuditEntry) {
+    if let Err(e) = append(&entry) {
+        eprintln!("Failed to write the action history: {}", e);
+    }
+    if let Some(listener) = RECORD_LISTENER.get() {
+        listener(&entry);
+    }
+}
+
+/// 条件に合う履歴（新しい順）
+pub fn query(query: &AuditQuery) -> Vec<AuditEntry> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let mut entries = Vec::new();
+    for generation in 0..=MAX_ROTATED {
+        let Ok(content) = fs::read_to_string(log_path(generation)) else {
+            continue;
+        };
+        // 書きかけの行や壊れた行は読み飛ばす
+        let matching = content
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+            .filter(|entry| query.matches(entry));
+        entries.extend(matching.take(limit - entries.len()));
+        if entries.len() >= limit {
+            break;
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TestConfigDir;
+
+    fn entry(timestamp: u64, button_id: &str, success: bool) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            device: None,
+            user: None,
+            session: "0123abcd".to_string(),
+            ip: "192.168.1.20".to_string(),
+            button_id: button_id.to_string(),
+            transport: Transport::Http,
+            success,
+            code: None,
+            error: None,
+            latency_ms: 1,
+        }
+    }
+
+    fn timestamps(entries: &[AuditEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.timestamp).collect()
+    }
+
+    #[test]
+    fn large_logs_are_rotated_keeping_three_generations() {
+        let _config_dir = TestConfigDir::new();
+        fs::write(log_path(0), "0".repeat(MAX_LOG_BYTES as usize)).unwrap();
+        for generation in 1..=MAX_ROTATED {
+            fs::write(log_path(generation), generation.to_string()).unwrap();
+        }
+
+        append(&entry(1, "copy", true)).unwrap();
+
+        assert_eq!(fs::metadata(log_path(1)).unwrap().len(), MAX_LOG_BYTES);
+        assert_eq!(fs::read_to_string(log_path(2)).unwrap(), "1");
+        // 一番古いファイルは消え、4世代目は作らない
+        assert_eq!(fs::read_to_string(log_path(3)).unwrap(), "2");
+        assert!(!log_path(MAX_ROTATED + 1).exists());
+        assert_eq!(timestamps(&query(&AuditQuery::default())), vec![1]);
+
+        // 小さいうちは同じファイルに追記する
+        append(&entry(2, "copy", true)).unwrap();
+        assert_eq!(timestamps(&query(&AuditQuery::default())), vec![2, 1]);
+    }
+
+    #[test]
+    fn queries_filter_newest_first_across_generations() {
+        let _config_dir = TestConfigDir::new();
+        let mut older = entry(1, "copy", true);
+        older.device = Some("Living Room iPad".to_string());
+        let older_line = serde_json::to_string(&older).unwrap();
+        // 書きかけの行は読み飛ばす
+        fs::write(log_path(1), format!("{}\n{{\"timestamp\":", older_line)).unwrap();
+
+        let mut guest = entry(2, "paste", false);
+        guest.user = Some("guest".to_string());
+        guest.ip = "192.168.1.30".to_string();
+        append(&guest).unwrap();
+        append(&entry(3, "copy", true)).unwrap();
+
+        let search = |query: AuditQuery| timestamps(&super::query(&query));
+        assert_eq!(search(AuditQuery::default()), vec![3, 2, 1]);
+        assert_eq!(
+            search(AuditQuery {
+                device: Some("ipad".to_string()),
+                ..AuditQuery::default()
+            }),
+            vec![1]
+        );
+        // 端末名がなければユーザー名とセッションIDで探す
+        assert_eq!(
+            search(AuditQuery {
+                device: Some("GUEST".to_string()),
+                ..AuditQuery::default()
+            }),
+            vec![2]
+        );
+        assert_eq!(
+            search(AuditQuery {
+                device: Some("0123".to_string()),
+                ..AuditQuery::default()
+            }),
+            vec![3, 2, 1]
+        );
+        assert_eq!(
+            search(AuditQuery {
+                button_id: Some("copy".to_string()),
+                ..AuditQuery::default()
+            }),
+            vec![3, 1]
+        );
+        assert_eq!(
+            search(AuditQuery {
+                ip: Some("192.168.1.30".to_string()),
+                ..AuditQuery::default()
+            }),
+            vec![2]
+        );
+        assert_eq!(
+            search(AuditQuery {
+                success: Some(false),
+                ..AuditQuery::default()
+            }),
+            vec![2]
+        );
+        assert_eq!(
+            search(AuditQuery {
+                since: Some(2),
+                until: Some(3),
+                ..AuditQuery::default()
+            }),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn queries_are_limited() {
+        let _config_dir = TestConfigDir::new();
+        let lines: Vec<String> = (0..MAX_LIMIT as u64 + 10)
+            .map(|timestamp| serde_json::to_string(&entry(timestamp, "copy", true)).unwrap())
+            .collect();
+        fs::write(log_path(0), lines.join("\n")).unwrap();
+
+        let limited = query(&AuditQuery {
+            limit: Some(2),
+            ..AuditQuery::default()
+        });
+        let last = MAX_LIMIT as u64 + 9;
+        assert_eq!(timestamps(&limited), vec![last, last - 1]);
+        assert_eq!(query(&AuditQuery::default()).len(), DEFAULT_LIMIT);
+        let capped = query(&AuditQuery {
+            limit: Some(usize::MAX),
+            ..AuditQuery::default()
+        });
+        assert_eq!(capped.len(), MAX_LIMIT);
+    }
+}
@@ -0,0 +1,239 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use subtle::ConstantTimeEq;
+
+use crate::config::{AppConfig, ButtonConfig, PageConfig, UserConfig, MAIN_PAGE_ID};
+
+/// PINのハッシュ（argon2のPHC文字列）か
+pub fn is_pin_hash(pin: &str) -> bool {
+    pin.starts_with("$argon2")
+}
+
+/// PINをソルト付きでハッシュにする
+pub fn hash_pin(pin: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash PIN: {}", e))
+}
+
+/// 入力されたPINが保存された値と一致するか
+///
+/// ハッシュにできなかった平文が残っていても定数時間で比較する
+fn verify_pin(pin: &str, stored: &str) -> bool {
+    if is_pin_hash(stored) {
+        PasswordHash::new(stored).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(pin.as_bytes(), &hash)
+                .is_ok()
+        })
+    } else {
+        bool::from(pin.as_bytes().ct_eq(stored.as_bytes()))
+    }
+}
+
+/// 平文のPINをハッシュに置き換える（置き換えたら `true`）
+///
+/// 同じPINでもソルトが違えば別のハッシュになるので、重複はハッシュにする前に確認する
+pub fn hash_plaintext_pins(config: &mut AppConfig) -> Result<bool, String> {
+    let owner = std::iter::once(("the owner".to_string(), config.pin.clone()));
+    let users = config
+        .users
+        .iter()
+        .map(|user| (format!("user {}", user.name), user.pin.clone()));
+    let credentials: Vec<(String, String)> = owner.chain(users).collect();
+
+    for (index, (name, pin)) in credentials.iter().enumerate() {
+        if pin.is_empty() || is_pin_hash(pin) {
+            continue;
+        }
+        let duplicate = credentials.iter().enumerate().find(|(other, (_, stored))| {
+            *other != index && !stored.is_empty() && verify_pin(pin, stored)
+        });
+        if let Some((_, (other_name, _))) = duplicate {
+            return Err(format!(
+                "The PIN of {} is the same as the PIN of {}",
+                name, other_name
+            ));
+        }
+    }
+
+    let mut changed = false;
+    let slots = std::iter::once(&mut config.pin).chain(config.users.iter_mut().map(|u| &mut u.pin));
+    for pin in slots {
+        if !pin.is_empty() && !is_pin_hash(pin) {
+            *pin = hash_pin(pin)?;
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
+/// 認証済みの利用者
+#[derive(Debug, Clone)]
+pub enum Principal {
+    /// `AppConfig.pin`（または認証なし）で接続した、全ボタンを使える利用者
+    Owner,
+    /// `AppConfig.users` の利用者
+    User(UserConfig),
+}
+
+impl Principal {
+    /// PINから利用者を特定（一致しなければ `None`）
+    ///
+    /// ハッシュの計算に時間がかかるので、非同期の処理からは `spawn_blocking` で呼ぶ
+    pub fn authenticate(config: &AppConfig, pin: &str) -> Option<Self> {
+        if config.pin.is_empty() && config.users.is_empty() {
+            return Some(Principal::Owner);
+        }
+        if !config.pin.is_empty() && verify_pin(pin, &config.pin) {
+            return Some(Principal::Owner);
+        }
+        config
+            .users
+            .iter()
+            .find(|user| !user.pin.is_empty() && verify_pin(pin, &user.pin))
+            .map(|user| Principal::User(user.clone()))
+    }
+
+    /// 全ボタンを使えるか（設定を変更する操作はこの利用者だけに許可する）
+    pub fn has_full_access(&self) -> bool {
+        match self {
+            Principal::Owner => true,
+            Principal::User(user) => user.pages.is_none() && user.buttons.is_none(),
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Principal::Owner => None,
+            Principal::User(user) => Some(&user.name),
+        }
+    }
+
+    fn can_see_page(&self, page_id: &str) -> bool {
+        match self {
+            Principal::User(user) if !self.has_full_access() => user
+                .pages
+                .as_ref()
+                .is_some_and(|pages| pages.iter().any(|p| p == page_id)),
+            _ => true,
+        }
+    }
+
+    fn can_use_button(&self, page_id: &str, button_id: &str) -> bool {
+        if self.can_see_page(page_id) {
+            return true;
+        }
+        match self {
+            Principal::User(user) => user
+                .buttons
+                .as_ref()
+                .is_some_and(|buttons| buttons.iter().any(|b| b == button_id)),
+            Principal::Owner => true,
+        }
+    }
+
+    /// 使えるボタンを探す（存在しても許可されていなければ `None`）
+    pub fn find_button<'a>(
+        &self,
+        config: &'a AppConfig,
+        button_id: &str,
+    ) -> Option<&'a ButtonConfig> {
+        std::iter::once((MAIN_PAGE_ID, &config.buttons))
+            .chain(config.pages.iter().map(|p| (p.id.as_str(), &p.buttons)))
+            .flat_map(|(page_id, buttons)| buttons.iter().map(move |b| (page_id, b)))
+            .find(|(_, b)| b.id == button_id)
+            .filter(|(page_id, b)| self.can_use_button(page_id, &b.id))
+            .map(|(_, b)| b)
+    }
+
+    /// 使えるボタンだけを残したページ（メインページを先頭に、空になったページは除く）
+    ///
+    /// メインページは空でも常に含める
+    pub fn visible_pages(&self, config: &AppConfig) -> Vec<PageConfig> {
+        config
+            .all_pages()
+            .into_iter()
+            .filter_map(|mut page| {
+                let page_id = page.id.clone();
+                page.buttons
+                    .retain(|b| self.can_use_button(&page_id, &b.id));
+                (page_id == MAIN_PAGE_ID || self.can_see_page(&page_id) || !page.buttons.is_empty())
+                    .then_some(page)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str, pin: &str) -> UserConfig {
+        UserConfig {
+            name: name.to_string(),
+            pin: pin.to_string(),
+            pages: None,
+            buttons: None,
+        }
+    }
+
+    fn config(pin: &str, users: Vec<UserConfig>) -> AppConfig {
+        AppConfig {
+            pin: pin.to_string(),
+            users,
+            ..AppConfig::default()
+        }
+    }
+
+    #[test]
+    fn plaintext_pins_are_hashed_and_still_accepted() {
+        let mut config = config("1111", vec![user("guest", "2222")]);
+        assert!(hash_plaintext_pins(&mut config).unwrap());
+        assert!(is_pin_hash(&config.pin));
+        assert!(is_pin_hash(&config.users[0].pin));
+
+        assert!(matches!(
+            Principal::authenticate(&config, "1111"),
+            Some(Principal::Owner)
+        ));
+        let guest = Principal::authenticate(&config, "2222").unwrap();
+        assert_eq!(guest.name(), Some("guest"));
+        assert!(Principal::authenticate(&config, "3333").is_none());
+
+        // 2回目は何も変えない
+        let hashed = config.clone();
+        assert!(!hash_plaintext_pins(&mut config).unwrap());
+        assert_eq!(config, hashed);
+    }
+
+    #[test]
+    fn new_pins_are_checked_against_existing_hashes() {
+        let mut hashed = config("1111", vec![user("guest", "2222")]);
+        hash_plaintext_pins(&mut hashed).unwrap();
+
+        // 設定画面で1人のPINだけ変えた（他はハッシュのまま）
+        let mut duplicate = hashed.clone();
+        duplicate.users.push(user("kid", "2222"));
+        let error = hash_plaintext_pins(&mut duplicate).unwrap_err();
+        assert!(error.contains("user kid"));
+        assert!(error.contains("user guest"));
+
+        let mut distinct = hashed.clone();
+        distinct.users.push(user("kid", "3333"));
+        assert!(hash_plaintext_pins(&mut distinct).unwrap());
+        assert_eq!(distinct.pin, hashed.pin);
+        assert_eq!(distinct.users[0].pin, hashed.users[0].pin);
+        assert!(is_pin_hash(&distinct.users[1].pin));
+    }
+
+    #[test]
+    fn duplicate_plaintext_pins_are_rejected() {
+        let mut config = config("1111", vec![user("guest", "1111")]);
+        assert!(hash_plaintext_pins(&mut config).is_err());
+        assert_eq!(config.pin, "1111");
+    }
+}
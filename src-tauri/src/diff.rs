@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ButtonConfig, GridConfig, PageConfig};
+
+/// ページ内の変更（スマホ側で再読み込みせずに反映できる範囲）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageDiff {
+    pub page_id: String,
+    /// 変更後のグリッド（変わっていなければ省略）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grid: Option<GridConfig>,
+    /// 変更後の横向きグリッド（変わっていなければ省略、`null` は削除）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landscape_grid: Option<Option<GridConfig>>,
+    /// 追加されたボタン
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<ButtonConfig>,
+    /// 内容が変わったボタン（変更後）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<ButtonConfig>,
+    /// 削除されたボタンのID
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl PageDiff {
+    fn is_empty(&self) -> bool {
+        self.grid.is_none()
+            && self.landscape_grid.is_none()
+            && self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+/// ページ一覧の差分（変更のないページは含めない）
+///
+/// ページの追加・削除・並び替えや、名前・テーマの変更は差分で表せないので `None`
+pub fn diff_pages(old: &[PageConfig], new: &[PageConfig]) -> Option<Vec<PageDiff>> {
+    if old.len() != new.len() {
+        return None;
+    }
+
+    let mut diffs = Vec::new();
+    for (old, new) in old.iter().zip(new) {
+        if old.id != new.id || old.name != new.name || old.theme != new.theme {
+            return None;
+        }
+        let diff = diff_page(old, new);
+        if !diff.is_empty() {
+            diffs.push(diff);
+        }
+    }
+    Some(diffs)
+}
+
+fn diff_page(old: &PageConfig, new: &PageConfig) -> PageDiff {
+    let find_old = |id: &str| old.buttons.iter().find(|b| b.id == id);
+
+    PageDiff {
+        page_id: new.id.clone(),
+        grid: (old.grid != new.grid).then(|| new.grid.clone()),
+        landscape_grid: (old.landscape_grid != new.landscape_grid)
+            .then(|| new.landscape_grid.clone()),
+        added: new
+            .buttons
+            .iter()
+            .filter(|b| find_old(&b.id).is_none())
+            .cloned()
+            .collect(),
+        changed: new
+            .buttons
+            .iter()
+            .filter(|b| find_old(&b.id).is_some_and(|old| old != *b))
+            .cloned()
+            .collect(),
+        removed: old
+            .buttons
+            .iter()
+            .filter(|b| !new.buttons.iter().any(|n| n.id == b.id))
+            .map(|b| b.id.clone())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn pages() -> Vec<PageConfig> {
+        vec![AppConfig::default().main_page()]
+    }
+
+    /// 1ページだけの差分
+    fn diff_one(old: &[PageConfig], new: &[PageConfig]) -> PageDiff {
+        let mut diffs = diff_pages(old, new).expect("expected a page diff");
+        assert_eq!(diffs.len(), 1);
+        diffs.remove(0)
+    }
+
+    #[test]
+    fn unchanged_pages_have_no_diff() {
+        assert_eq!(diff_pages(&pages(), &pages()), Some(Vec::new()));
+
+        // ボタンの並び順だけの違いは表示に影響しない
+        let mut new = pages();
+        new[0].buttons.reverse();
+        assert_eq!(diff_pages(&pages(), &new), Some(Vec::new()));
+    }
+
+    #[test]
+    fn added_and_removed_buttons() {
+        let old = pages();
+        let mut new = pages();
+        let removed = new[0].buttons.remove(0);
+        let mut added = removed.clone();
+        added.id = "added".to_string();
+        new[0].buttons.push(added.clone());
+
+        let diff = diff_one(&old, &new);
+        assert_eq!(diff.page_id, "main");
+        assert_eq!(diff.added, vec![added]);
+        assert_eq!(diff.removed, vec![removed.id]);
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn moved_and_changed_buttons() {
+        let old = pages();
+        let mut new = pages();
+        new[0].buttons[0].position.x += 1;
+        new[0].buttons[1].label = "Renamed".to_string();
+
+        let diff = diff_one(&old, &new);
+        assert_eq!(
+            diff.changed,
+            vec![new[0].buttons[0].clone(), new[0].buttons[1].clone()]
+        );
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.grid, None);
+    }
+
+    #[test]
+    fn grid_changes() {
+        let old = pages();
+        let mut new = pages();
+        new[0].grid.rows += 1;
+        new[0].landscape_grid = Some(new[0].grid.clone());
+
+        let diff = diff_one(&old, &new);
+        assert_eq!(diff.grid, Some(new[0].grid.clone()));
+        assert_eq!(diff.landscape_grid, Some(Some(new[0].grid.clone())));
+
+        // 横向きグリッドの削除は `Some(None)`
+        let diff = diff_one(&new, &old);
+        assert_eq!(diff.landscape_grid, Some(None));
+    }
+
+    #[test]
+    fn page_structure_changes_cannot_be_diffed() {
+        let old = pages();
+
+        let mut added = pages();
+        added.push(PageConfig {
+            id: "second".to_string(),
+            ..old[0].clone()
+        });
+        assert_eq!(diff_pages(&old, &added), None);
+
+        let mut renamed = pages();
+        renamed[0].name = "Renamed".to_string();
+        assert_eq!(diff_pages(&old, &renamed), None);
+    }
+}
@@ -0,0 +1,184 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::fmt;
+
+use crate::actions::ActionError;
+use crate::keyboard::KeyboardError;
+
+/// APIのエラー（`code` でクライアントが種類を見分けられるようにする）
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// PIN（またはログインチケット）が違う
+    InvalidCredentials,
+    /// セッションが期限切れ・失効している
+    InvalidSession,
+    /// PCの設定ファイルを読み込めていない（デフォルト設定では認証させない）
+    ConfigBroken,
+    /// 試行が多すぎる（`retry_after` は再試行できるまでの秒数）
+    RateLimited {
+        message: String,
+        retry_after: Option<u64>,
+    },
+    /// 許可していないオリジンのページからのリクエスト
+    OriginNotAllowed,
+    /// 利用者に許可されていない操作
+    Forbidden(String),
+    ButtonNotFound(String),
+    NotFound(String),
+    /// リクエストの内容が正しくない
+    InvalidRequest(String),
+    /// ボタンの設定に知らないキー名がある
+    UnknownKey(String),
+    /// ボタンのアクションの設定が正しくない（メインキーがない、変数が宣言されていないなど）
+    InvalidAction(String),
+    /// PCでキー入力を送れない（Macのアクセシビリティ権限がないなど）
+    InputBackendUnavailable(String),
+    /// キー入力の送信中に失敗した
+    InputFailed(String),
+    Internal(String),
+}
+
+impl ApiError {
+    /// 機械可読なエラーコード
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::InvalidSession => "invalid_session",
+            ApiError::ConfigBroken => "config_broken",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::OriginNotAllowed => "origin_not_allowed",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::ButtonNotFound(_) => "button_not_found",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::UnknownKey(_) => "unknown_key",
+            ApiError::InvalidAction(_) => "invalid_action",
+            ApiError::InputBackendUnavailable(_) => "input_backend_unavailable",
+            ApiError::InputFailed(_) => "input_failed",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidCredentials | ApiError::InvalidSession => StatusCode::UNAUTHORIZED,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::OriginNotAllowed | ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::ButtonNotFound(_) | ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            // リクエストは正しいが、PCにあるボタンの設定のせいで実行できない
+            ApiError::UnknownKey(_) | ApiError::InvalidAction(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::ConfigBroken | ApiError::InputBackendUnavailable(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::InputFailed(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidCredentials => write!(f, "Invalid PIN"),
+            ApiError::InvalidSession => write!(f, "Session expired or invalid"),
+            ApiError::ConfigBroken => write!(
+                f,
+                "The config file on the computer could not be loaded. Fix it in TapKey's settings"
+            ),
+            ApiError::RateLimited { message, .. } => write!(f, "{}", message),
+            ApiError::OriginNotAllowed => write!(f, "Origin not allowed"),
+            ApiError::ButtonNotFound(id) => write!(f, "Button not found: {}", id),
+            ApiError::UnknownKey(key) => write!(f, "Unknown key: {}", key),
+            ApiError::InputBackendUnavailable(e) => {
+                write!(f, "Keyboard input is unavailable: {}", e)
+            }
+            ApiError::InputFailed(e) => write!(f, "Action failed: {}", e),
+            ApiError::InvalidAction(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::InvalidRequest(message)
+            | ApiError::Internal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<ActionError> for ApiError {
+    fn from(e: ActionError) -> Self {
+        match e {
+            ActionError::Keyboard(KeyboardError::Unavailable(e)) => {
+                ApiError::InputBackendUnavailable(e)
+            }
+            ActionError::Keyboard(KeyboardError::UnknownKey(key)) => ApiError::UnknownKey(key),
+            ActionError::Keyboard(e @ KeyboardError::NoMainKey) => {
+                ApiError::InvalidAction(e.to_string())
+            }
+            ActionError::Keyboard(KeyboardError::Failed(e)) => ApiError::InputFailed(e),
+            ActionError::Variable(e) => ApiError::InvalidAction(e),
+        }
+    }
+}
+
+/// エラーのレスポンス（成功時のレスポンスと同じく `success` と `message` を持つ）
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    success: bool,
+    code: &'static str,
+    message: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(ErrorResponse {
+            success: false,
+            code: self.code(),
+            message: self.to_string(),
+        });
+        match &self {
+            ApiError::RateLimited {
+                retry_after: Some(seconds),
+                ..
+            } => (
+                self.status(),
+                [(header::RETRY_AFTER, seconds.to_string())],
+                body,
+            )
+                .into_response(),
+            _ => (self.status(), body).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyboard_errors_are_told_apart() {
+        let unknown: ApiError =
+            ActionError::Keyboard(KeyboardError::UnknownKey("foo".into())).into();
+        assert_eq!(unknown.code(), "unknown_key");
+        assert_eq!(unknown.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let unavailable: ApiError =
+            ActionError::Keyboard(KeyboardError::Unavailable("no display".into())).into();
+        assert_eq!(unavailable.code(), "input_backend_unavailable");
+        assert_eq!(unavailable.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn rate_limit_sets_retry_after() {
+        let response = ApiError::RateLimited {
+            message: "Too many attempts".to_string(),
+            retry_after: Some(30),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    }
+}
@@ -1,13 +1,34 @@
-use crate::config::ButtonAction;
 use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use std::fmt;
+
+/// キー入力の失敗
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyboardError {
+    /// キー入力を送れない（Macのアクセシビリティ権限がない、ディスプレイに接続できないなど）
+    Unavailable(String),
+    /// ボタンの設定に知らないキー名がある
+    UnknownKey(String),
+    /// ショートカットにモディファイア以外のキーがない
+    NoMainKey,
+    /// キー入力の送信中に失敗した
+    Failed(String),
+}
 
-pub fn execute_action(action: &ButtonAction) -> Result<(), String> {
-    match action {
-        ButtonAction::Shortcut { keys } => execute_shortcut(keys),
-        ButtonAction::TextAndEnter { text } => execute_text_and_enter(text),
+impl fmt::Display for KeyboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyboardError::Unavailable(e) => write!(f, "Failed to create Enigo: {}", e),
+            KeyboardError::UnknownKey(key) => write!(f, "Unknown key: {}", key),
+            KeyboardError::NoMainKey => write!(f, "No main key specified"),
+            KeyboardError::Failed(e) => write!(f, "{}", e),
+        }
     }
 }
 
+fn new_enigo() -> Result<Enigo, KeyboardError> {
+    Enigo::new(&Settings::default()).map_err(|e| KeyboardError::Unavailable(format!("{:?}", e)))
+}
+
 fn key_from_string(key: &str) -> Option<Key> {
     match key.to_lowercase().as_str() {
         // Modifiers
@@ -58,9 +79,8 @@ fn is_modifier(key: &Key) -> bool {
     matches!(key, Key::Meta | Key::Control | Key::Alt | Key::Shift)
 }
 
-fn execute_shortcut(keys: &[String]) -> Result<(), String> {
-    let mut enigo = Enigo::new(&Settings::default())
-        .map_err(|e| format!("Failed to create Enigo: {:?}", e))?;
+pub fn execute_shortcut(keys: &[String]) -> Result<(), KeyboardError> {
+    let mut enigo = new_enigo()?;
 
     // まず全てのモディファイアキーをリリースして状態をクリア
     let all_modifiers = [Key::Meta, Key::Control, Key::Alt, Key::Shift];
@@ -80,52 +100,51 @@ fn execute_shortcut(keys: &[String]) -> Result<(), String> {
                 main_key = Some(key);
             }
         } else {
-            return Err(format!("Unknown key: {}", key_str));
+            return Err(KeyboardError::UnknownKey(key_str.clone()));
         }
     }
 
-    let main_key = main_key.ok_or("No main key specified")?;
+    let main_key = main_key.ok_or(KeyboardError::NoMainKey)?;
 
     // モディファイアがない場合は単純にClickを使う
     if modifier_keys.is_empty() {
         enigo.key(main_key, Direction::Click)
-            .map_err(|e| format!("Failed to click key: {:?}", e))?;
+            .map_err(|e| KeyboardError::Failed(format!("Failed to click key: {:?}", e)))?;
         return Ok(());
     }
 
     // Press modifiers
     for key in &modifier_keys {
         enigo.key(*key, Direction::Press)
-            .map_err(|e| format!("Failed to press modifier: {:?}", e))?;
+            .map_err(|e| KeyboardError::Failed(format!("Failed to press modifier: {:?}", e)))?;
         std::thread::sleep(std::time::Duration::from_millis(20));
     }
 
     // Press and release main key
     enigo.key(main_key, Direction::Click)
-        .map_err(|e| format!("Failed to click key: {:?}", e))?;
+        .map_err(|e| KeyboardError::Failed(format!("Failed to click key: {:?}", e)))?;
     std::thread::sleep(std::time::Duration::from_millis(20));
 
     // Release modifiers in reverse order
     for key in modifier_keys.iter().rev() {
         enigo.key(*key, Direction::Release)
-            .map_err(|e| format!("Failed to release modifier: {:?}", e))?;
+            .map_err(|e| KeyboardError::Failed(format!("Failed to release modifier: {:?}", e)))?;
         std::thread::sleep(std::time::Duration::from_millis(20));
     }
 
     Ok(())
 }
 
-fn execute_text_and_enter(text: &str) -> Result<(), String> {
-    let mut enigo = Enigo::new(&Settings::default())
-        .map_err(|e| format!("Failed to create Enigo: {:?}", e))?;
+pub fn execute_text_and_enter(text: &str) -> Result<(), KeyboardError> {
+    let mut enigo = new_enigo()?;
 
     // Type text
     enigo.text(text)
-        .map_err(|e| format!("Failed to type text: {:?}", e))?;
+        .map_err(|e| KeyboardError::Failed(format!("Failed to type text: {:?}", e)))?;
 
     // Press Enter
     enigo.key(Key::Return, Direction::Click)
-        .map_err(|e| format!("Failed to press Enter: {:?}", e))?;
+        .map_err(|e| KeyboardError::Failed(format!("Failed to press Enter: {:?}", e)))?;
 
     Ok(())
 }
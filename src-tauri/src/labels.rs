@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::config::{AppConfig, DynamicLabel, LabelSource};
+use crate::server;
+
+/// 評価ループの刻み
+const TICK: Duration = Duration::from_millis(100);
+/// コマンドの実行時間の上限
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// ラベルに表示する最大文字数
+const MAX_LABEL_CHARS: usize = 64;
+
+/// 直近に評価したラベル（ボタンID → ラベル）
+static CURRENT_LABELS: once_cell::sync::Lazy<RwLock<HashMap<String, String>>> =
+    once_cell::sync::Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// 直近に評価したラベル（新しく接続したクライアントに送る）
+pub fn current_labels() -> HashMap<String, String> {
+    CURRENT_LABELS.read().unwrap().clone()
+}
+
+/// ボタンごとの評価状態
+struct LabelState {
+    label: DynamicLabel,
+    next_due: Instant,
+    counter: i64,
+    /// 実行中のコマンド（遅いコマンドで他のラベルの更新が止まらないよう別タスクで実行する）
+    command: Option<JoinHandle<String>>,
+}
+
+impl LabelState {
+    fn new(label: DynamicLabel) -> Self {
+        let counter = match label.source {
+            LabelSource::Counter { start, .. } => start,
+            _ => 0,
+        };
+        Self {
+            label,
+            next_due: Instant::now(),
+            counter,
+            command: None,
+        }
+    }
+
+    /// 評価の時間になっていれば評価する（新しいラベルができなければ `None`）
+    ///
+    /// コマンドは別タスクで実行し、終わった後の呼び出しで結果を返す
+    async fn poll(&mut self, now: Instant) -> Option<String> {
+        if let Some(command) = self.command.take_if(|command| command.is_finished()) {
+            let value = command.await.unwrap_or_else(|_| "?".to_string());
+            return Some(self.render(&value));
+        }
+        if self.command.is_some() || self.next_due > now {
+            return None;
+        }
+        self.next_due = now + Duration::from_millis(self.label.interval_ms as u64);
+
+        let value = match &self.label.source {
+            LabelSource::Clock { format } => {
+                // 不正な書式は to_string() だとpanicするので write! で受ける
+                let mut text = String::new();
+                match write!(text, "{}", chrono::Local::now().format(format)) {
+                    Ok(()) => text,
+                    Err(_) => "?".to_string(),
+                }
+            }
+            LabelSource::Counter { step, .. } => {
+                let value = self.counter;
+                self.counter = self.counter.saturating_add(*step);
+                value.to_string()
+            }
+            LabelSource::GitBranch { path } => git_branch(path).unwrap_or_else(|| "?".to_string()),
+            LabelSource::Command { command } => {
+                let command = command.clone();
+                self.command = Some(tokio::spawn(async move {
+                    run_command(&command).await.unwrap_or_else(|e| {
+                        eprintln!("Dynamic label command failed: {}", e);
+                        "?".to_string()
+                    })
+                }));
+                return None;
+            }
+        };
+        Some(self.render(&value))
+    }
+
+    fn render(&self, value: &str) -> String {
+        // テンプレート中の {変数名} も変数の値に置き換える
+        crate::variables::interpolate(&self.label.template)
+            .replace("{value}", value)
+            .chars()
+            .take(MAX_LABEL_CHARS)
+            .collect()
+    }
+}
+
+impl Drop for LabelState {
+    /// 削除・変更されたラベルのコマンドは待たずに止める
+    fn drop(&mut self) {
+        if let Some(command) = &self.command {
+            command.abort();
+        }
+    }
+}
+
+/// 設定中の動的ラベルを評価し続け、変化したラベルを `on_update` に渡す
+pub async fn run<F: Fn(HashMap<String, String>)>(on_update: F) {
+    let mut states: HashMap<String, LabelState> = HashMap::new();
+    let mut ticker = tokio::time::interval(TICK);
+
+    loop {
+        ticker.tick().await;
+        let config = server::get_cached_config();
+        let labels = dynamic_labels(&config);
+
+        // 削除・変更されたラベルは状態を作り直す
+        states.retain(|id, state| labels.get(id.as_str()) == Some(&&state.label));
+
+        let mut updates = HashMap::new();
+        for (id, label) in labels {
+            let state = states
+                .entry(id.to_string())
+                .or_insert_with(|| LabelState::new(label.clone()));
+            let Some(text) = state.poll(Instant::now()).await else {
+                continue;
+            };
+            if CURRENT_LABELS.read().unwrap().get(id) != Some(&text) {
+                updates.insert(id.to_string(), text);
+            }
+        }
+
+        {
+            let mut current = CURRENT_LABELS.write().unwrap();
+            current.retain(|id, _| states.contains_key(id));
+            current.extend(updates.clone());
+        }
+        if !updates.is_empty() {
+            on_update(updates);
+        }
+    }
+}
+
+/// 全ページの動的ラベル（ボタンID → 設定）
+fn dynamic_labels(config: &AppConfig) -> HashMap<&str, &DynamicLabel> {
+    config
+        .buttons
+        .iter()
+        .chain(config.pages.iter().flat_map(|page| &page.buttons))
+        .filter_map(|b| Some((b.id.as_str(), b.dynamic_label.as_ref()?)))
+        .collect()
+}
+
+/// `.git/HEAD` から現在のブランチ名を読む（相対パスは設定ディレクトリ基準）
+fn git_branch(path: &str) -> Option<String> {
+    let repo = AppConfig::config_dir().join(path);
+    let mut git_dir = repo.join(".git");
+    // worktreeやsubmoduleでは .git が "gitdir: <path>" を書いたファイルになる
+    if git_dir.is_file() {
+        let content = fs::read_to_string(&git_dir).ok()?;
+        let target = PathBuf::from(content.strip_prefix("gitdir:")?.trim());
+        git_dir = repo.join(target);
+    }
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        // detached HEAD は短縮ハッシュを表示
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// コマンドを実行して出力の1行目を返す
+async fn run_command(command: &str) -> Result<String, String> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    cmd.kill_on_drop(true);
+
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, cmd.output())
+        .await
+        .map_err(|_| format!("timed out: {}", command))?
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("{} exited with {}", command, output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(source: LabelSource) -> LabelState {
+        LabelState::new(DynamicLabel {
+            source,
+            template: "{value}".to_string(),
+            interval_ms: 1000,
+        })
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn slow_commands_do_not_hold_up_other_labels() {
+        let started = Instant::now();
+        let mut slow = state(LabelSource::Command {
+            command: "sleep 5".to_string(),
+        });
+        let mut counter = state(LabelSource::Counter { start: 1, step: 1 });
+
+        assert_eq!(slow.poll(Instant::now()).await, None);
+        assert_eq!(counter.poll(Instant::now()).await.as_deref(), Some("1"));
+        // 実行中は次の評価を始めない
+        assert_eq!(
+            slow.poll(Instant::now() + Duration::from_secs(2)).await,
+            None
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn command_output_is_returned_when_it_finishes() {
+        let mut label = state(LabelSource::Command {
+            command: "echo main".to_string(),
+        });
+        assert_eq!(label.poll(Instant::now()).await, None);
+        let text = loop {
+            if let Some(text) = label.poll(Instant::now()).await {
+                break text;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(text, "main");
+    }
+}
@@ -1,14 +1,35 @@
+mod actions;
+mod assets;
+mod audit;
+mod auth;
+mod bundle;
 mod config;
+mod diff;
+mod error;
 mod keyboard;
+mod labels;
+mod network;
+mod origin;
+mod pairing;
 mod qr;
+mod ratelimit;
+mod schema;
 mod server;
+mod session;
+mod tls;
+mod variables;
+mod watcher;
 
-use config::AppConfig;
+use bundle::{ConflictStrategy, ImportPreview, LayoutBundle};
+use config::{AppConfig, ConfigBackup, ConfigFormat, ConfigLoadError, ConfigLocation};
 use tauri::{
     menu::{Menu, MenuItem},
     tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
-    Emitter, Manager,
+    AppHandle, Emitter, Manager,
 };
+use tauri_plugin_notification::NotificationExt;
+
+const TRAY_ID: &str = "main";
 
 #[tauri::command]
 fn get_config() -> AppConfig {
@@ -17,58 +38,310 @@ fn get_config() -> AppConfig {
 
 #[tauri::command]
 fn save_config(config: AppConfig) -> Result<(), String> {
-    config.save().map_err(|e| e.to_string())?;
     // WebSocket経由で全クライアントに設定更新を通知
-    server::notify_config_updated();
+    server::update_config(&config)
+}
+
+#[tauri::command]
+fn get_config_load_error() -> Option<ConfigLoadError> {
+    server::get_load_error()
+}
+
+/// 設定ファイルを再読み込み（ユーザーがファイルを修正した後）
+#[tauri::command]
+fn retry_config_load(app: AppHandle) -> Result<(), ConfigLoadError> {
+    let result = server::notify_config_updated();
+    refresh_tray(&app);
+    result
+}
+
+/// 壊れた設定ファイルを破棄し、現在の設定で上書き（退避したバックアップは残る）
+#[tauri::command]
+fn discard_broken_config(app: AppHandle) -> Result<(), String> {
+    server::discard_load_error();
+    server::get_cached_config()
+        .save()
+        .map_err(|e| e.to_string())?;
+    refresh_tray(&app);
     Ok(())
 }
 
+/// 設定ファイルの場所（標準 / 指定ファイル / ポータブル）とパス
+#[tauri::command]
+fn get_config_location() -> (ConfigLocation, String) {
+    (
+        ConfigLocation::current().clone(),
+        AppConfig::config_path().display().to_string(),
+    )
+}
+
+#[tauri::command]
+fn get_config_format() -> ConfigFormat {
+    AppConfig::config_format()
+}
+
+/// 設定ファイルを TOML / YAML / JSONC / JSON に変換
+#[tauri::command]
+fn convert_config_format(format: ConfigFormat) -> Result<String, String> {
+    let path = server::convert_config_format(format)?;
+    Ok(path.display().to_string())
+}
+
+#[tauri::command]
+fn list_config_backups() -> Vec<ConfigBackup> {
+    AppConfig::list_backups()
+}
+
+/// バックアップから設定を復元し、全クライアントに通知
+#[tauri::command]
+fn restore_config_backup(app: AppHandle, name: String) -> Result<(), String> {
+    let result = server::restore_backup(&name);
+    refresh_tray(&app);
+    result
+}
+
+/// ページ（省略時は全ページ）をバンドルファイルに書き出し、書き出したパスを返す
+#[tauri::command]
+fn export_bundle(page_id: Option<String>, path: Option<String>) -> Result<String, String> {
+    let bundle = LayoutBundle::export(&server::get_cached_config(), page_id.as_deref())?;
+    let path = bundle::write_bundle_file(&bundle, path.map(Into::into))?;
+    Ok(path.display().to_string())
+}
+
+#[tauri::command]
+fn preview_bundle_import(
+    bundle: LayoutBundle,
+    strategy: ConflictStrategy,
+) -> Result<ImportPreview, String> {
+    bundle
+        .plan_import(&server::get_cached_config(), strategy)
+        .map(|(_, preview)| preview)
+}
+
+#[tauri::command]
+fn import_bundle(bundle: LayoutBundle, strategy: ConflictStrategy) -> Result<ImportPreview, String> {
+    server::apply_bundle(&bundle, strategy)
+}
+
+/// JSON Schemaを書き出し、設定ファイルから参照されていなければ `$schema` を追加する
+#[tauri::command]
+fn write_config_schema(path: Option<String>) -> Result<String, String> {
+    let custom_path = path.is_some();
+    let path = schema::write_schema_file(path.map(Into::into))?;
+
+    let config = server::get_cached_config();
+    if !custom_path && config.schema.is_none() {
+        let config = AppConfig {
+            schema: Some(format!("./{}", schema::SCHEMA_FILE_NAME)),
+            ..config
+        };
+        server::update_config(&config)?;
+    }
+    Ok(path.display().to_string())
+}
+
+/// アイコン画像を保存し、`ButtonIcon::Asset` で参照する保存名を返す
+#[tauri::command]
+fn upload_icon(file_name: String, data: String) -> Result<String, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let data = STANDARD.decode(data).map_err(|e| e.to_string())?;
+    assets::store_asset(&file_name, &data)
+}
+
+#[tauri::command]
+fn list_builtin_icons() -> Vec<&'static str> {
+    assets::builtin_icon_names()
+}
+
+/// 変数の現在値
+#[tauri::command]
+fn get_variables() -> std::collections::BTreeMap<String, config::VariableValue> {
+    variables::values()
+}
+
+/// 全変数を初期値に戻す
+#[tauri::command]
+fn reset_variables() -> Result<(), String> {
+    variables::reset_all()
+}
+
+/// 全スマホのセッションと承認済みの端末を失効させる（次の操作でPINの入力が必要になる）
+#[tauri::command]
+fn revoke_sessions() -> Result<(), String> {
+    session::revoke_all()
+}
+
+/// 承認待ちのペアリング要求
+#[tauri::command]
+fn list_pairing_requests() -> Vec<pairing::PairingRequest> {
+    pairing::pending()
+}
+
+/// ペアリング要求を承認（スマホは次の問い合わせで端末のトークンを受け取る）
+#[tauri::command]
+fn approve_pairing(app: AppHandle, id: String) -> Result<(), String> {
+    let result = pairing::approve(&id);
+    refresh_tray(&app);
+    result
+}
+
+/// ペアリング要求を拒否
+#[tauri::command]
+fn deny_pairing(app: AppHandle, id: String) {
+    pairing::deny(&id);
+    refresh_tray(&app);
+}
+
+/// 承認済みの端末
+#[tauri::command]
+fn list_devices() -> Vec<session::TrustedDevice> {
+    session::list_devices()
+}
+
+/// 端末の承認を取り消し、接続中ならすぐに切断する
+#[tauri::command]
+fn revoke_device(id: String) -> Result<(), String> {
+    session::revoke_device(&id)
+}
+
+/// 操作履歴（新しい順）
+#[tauri::command]
+fn get_action_history(query: audit::AuditQuery) -> Vec<audit::AuditEntry> {
+    audit::query(&query)
+}
+
 #[tauri::command]
 fn get_server_url() -> String {
     let config = server::get_cached_config();
-    qr::get_server_url(config.port)
+    qr::get_server_url(&config)
 }
 
 #[tauri::command]
 fn get_qr_code() -> Result<String, String> {
     let config = server::get_cached_config();
-    let url = qr::get_server_url_with_pin(config.port, &config.pin);
+    let url = qr::get_server_url_with_ticket(&config)?;
     qr::generate_qr_code(&url)
 }
 
+/// HTTPSの証明書のフィンガープリント（`AB:CD:...` 形式、HTTPSを使っていなければ `None`）
+#[tauri::command]
+fn get_certificate_fingerprint() -> Result<Option<String>, String> {
+    if !server::get_cached_config().https {
+        return Ok(None);
+    }
+    tls::fingerprint().map(|fp| Some(tls::format_fingerprint(&fp)))
+}
+
+/// HTTPSの証明書を作り直し、新しいフィンガープリントを返す（待ち受け中のサーバーにもすぐ反映）
+#[tauri::command]
+fn rotate_certificate() -> Result<String, String> {
+    tls::rotate().map(|fp| tls::format_fingerprint(&fp))
+}
+
 #[tauri::command]
 fn get_local_ip() -> Option<String> {
-    qr::get_local_ip().map(|ip| ip.to_string())
+    network::get_local_ip().map(|ip| ip.to_string())
+}
+
+/// 待ち受けアドレスの選択肢（ネットワークインターフェース一覧）
+#[tauri::command]
+fn list_network_interfaces() -> Vec<network::NetworkInterface> {
+    network::list_interfaces()
+}
+
+/// トレイメニューを作成（設定の読み込みエラーがあればその項目も表示）
+fn build_tray_menu(app: &AppHandle) -> tauri::Result<Menu<tauri::Wry>> {
+    let quit_item = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+    let settings_item = MenuItem::with_id(app, "settings", "Settings...", true, None::<&str>)?;
+    let show_qr_item = MenuItem::with_id(app, "show_qr", "Show QR Code", true, None::<&str>)?;
+
+    let menu = if server::get_load_error().is_some() {
+        let error_item =
+            MenuItem::with_id(app, "config_error", "⚠ Config Load Failed...", true, None::<&str>)?;
+        Menu::with_items(app, &[&error_item, &show_qr_item, &settings_item, &quit_item])?
+    } else {
+        Menu::with_items(app, &[&show_qr_item, &settings_item, &quit_item])?
+    };
+    // 承認待ちのペアリング要求があれば先頭に出す
+    let pending = pairing::pending();
+    if !pending.is_empty() {
+        let label = match pending.as_slice() {
+            [request] => format!("⚠ Pairing Request from \"{}\"...", request.name),
+            _ => format!("⚠ {} Pairing Requests...", pending.len()),
+        };
+        let pairing_item = MenuItem::with_id(app, "pairing", label, true, None::<&str>)?;
+        menu.prepend(&pairing_item)?;
+    }
+    // PIN認証のロックアウト中は表示だけの項目を先頭に出す
+    if ratelimit::is_locked_out() {
+        let lockout_item =
+            MenuItem::with_id(app, "auth_lockout", "⚠ PIN Attempts Locked Out", false, None::<&str>)?;
+        menu.prepend(&lockout_item)?;
+    }
+    Ok(menu)
+}
+
+/// トレイのメニューとツールチップを現在の状態に合わせて更新
+fn refresh_tray(app: &AppHandle) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    if let Ok(menu) = build_tray_menu(app) {
+        tray.set_menu(Some(menu)).ok();
+    }
+    let load_error = server::get_load_error();
+    let tooltip = if load_error.as_ref().is_some_and(|e| e.using_defaults) {
+        "TapKey - config file could not be loaded, phones cannot connect"
+    } else if load_error.is_some() {
+        "TapKey - config file has errors, the last valid config is in use"
+    } else if ratelimit::is_locked_out() {
+        "TapKey - too many wrong PINs, authentication is locked"
+    } else if !pairing::pending().is_empty() {
+        "TapKey - a phone is waiting for pairing approval"
+    } else {
+        "TapKey"
+    };
+    tray.set_tooltip(Some(tooltip)).ok();
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // --config / TAPKEY_CONFIG / ポータブルモードから設定ファイルの場所を決める
+    ConfigLocation::from_env().init();
+
     // キャッシュから設定を読み込み（初回アクセスでキャッシュ初期化）
     let config = server::get_cached_config();
     let port = config.port;
+    let listen = config.listen.clone();
+    let https = config.https;
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_notification::init())
         .setup(move |app| {
-            // HTTPサーバーを別スレッドで起動
+            // HTTPサーバーを別スレッドで起動（起動できなければスマホから繋がらないので通知する）
+            let handle = app.handle().clone();
             std::thread::spawn(move || {
                 let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
                 rt.block_on(async {
-                    if let Err(e) = server::start_server(port).await {
+                    if let Err(e) = server::start_server(listen, port, https).await {
                         eprintln!("HTTP Server error: {}", e);
+                        handle
+                            .notification()
+                            .builder()
+                            .title("TapKey: server is not running")
+                            .body(format!("Phones cannot connect: {}", e))
+                            .show()
+                            .ok();
                     }
                 });
             });
 
             // Create tray menu
-            let quit_item = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
-            let settings_item = MenuItem::with_id(app, "settings", "Settings...", true, None::<&str>)?;
-            let show_qr_item = MenuItem::with_id(app, "show_qr", "Show QR Code", true, None::<&str>)?;
-
-            let menu = Menu::with_items(app, &[&show_qr_item, &settings_item, &quit_item])?;
+            let menu = build_tray_menu(app.handle())?;
 
             // トレイアイコンを作成
-            let _tray = TrayIconBuilder::new()
+            let _tray = TrayIconBuilder::with_id(TRAY_ID)
                 .icon(app.default_window_icon().unwrap().clone())
                 .menu(&menu)
                 .show_menu_on_left_click(false)
@@ -89,6 +362,22 @@ pub fn run() {
                             window.emit("show-qr", ()).ok();
                         }
                     }
+                    "pairing" => {
+                        if let Some(window) = app.get_webview_window("main") {
+                            window.show().ok();
+                            window.set_focus().ok();
+                            window.emit("pairing-requested", ()).ok();
+                        }
+                    }
+                    "config_error" => {
+                        if let Some(window) = app.get_webview_window("main") {
+                            window.show().ok();
+                            window.set_focus().ok();
+                            window
+                                .emit("config-load-error", server::get_load_error())
+                                .ok();
+                        }
+                    }
                     _ => {}
                 })
                 .on_tray_icon_event(|tray, event| {
@@ -106,6 +395,78 @@ pub fn run() {
                     }
                 })
                 .build(app)?;
+            refresh_tray(app.handle());
+
+            // PIN認証がロックアウトされたらトレイに表示して通知（解除されたら表示を戻す）
+            let handle = app.handle().clone();
+            ratelimit::on_lockout(move |lockout| {
+                refresh_tray(&handle);
+                let source = match lockout.ip {
+                    Some(ip) => ip.to_string(),
+                    None => "multiple devices".to_string(),
+                };
+                handle
+                    .notification()
+                    .builder()
+                    .title("TapKey: too many wrong PINs")
+                    .body(format!(
+                        "PIN authentication from {} is locked for {} seconds.",
+                        source,
+                        lockout.duration.as_secs()
+                    ))
+                    .show()
+                    .ok();
+
+                let handle = handle.clone();
+                let duration = lockout.duration;
+                std::thread::spawn(move || {
+                    std::thread::sleep(duration);
+                    refresh_tray(&handle);
+                });
+            });
+
+            // ペアリング要求が届いたら設定画面とトレイに表示して通知（期限が切れたら表示を戻す）
+            // 同じスマホからの送り直しは設定画面の表示を更新するだけ（期限は最初の要求のまま）
+            let handle = app.handle().clone();
+            pairing::on_request(move |request, replaced| {
+                handle.emit("pairing-requested", ()).ok();
+                if replaced {
+                    return;
+                }
+                refresh_tray(&handle);
+                handle
+                    .notification()
+                    .builder()
+                    .title("TapKey: pairing request")
+                    .body(format!(
+                        "\"{}\" ({}) wants to connect. Code: {}",
+                        request.name, request.address, request.code
+                    ))
+                    .show()
+                    .ok();
+
+                let handle = handle.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(pairing::PAIRING_TTL);
+                    refresh_tray(&handle);
+                });
+            });
+
+            // スマホからの操作を設定画面の操作履歴に流す
+            let handle = app.handle().clone();
+            audit::on_record(move |entry| {
+                handle.emit("action-recorded", entry).ok();
+            });
+
+            // 外部エディタ等による設定ファイルの変更を監視
+            let handle = app.handle().clone();
+            watcher::start(move |result| {
+                refresh_tray(&handle);
+                match result {
+                    Ok(()) => handle.emit("config-reloaded", ()).ok(),
+                    Err(e) => handle.emit("config-load-error", Some(e)).ok(),
+                };
+            });
 
             Ok(())
         })
@@ -114,7 +475,33 @@ pub fn run() {
             save_config,
             get_server_url,
             get_qr_code,
-            get_local_ip
+            get_local_ip,
+            list_network_interfaces,
+            get_config_load_error,
+            retry_config_load,
+            discard_broken_config,
+            list_config_backups,
+            restore_config_backup,
+            export_bundle,
+            preview_bundle_import,
+            import_bundle,
+            write_config_schema,
+            get_config_location,
+            get_config_format,
+            convert_config_format,
+            upload_icon,
+            list_builtin_icons,
+            get_variables,
+            reset_variables,
+            revoke_sessions,
+            list_pairing_requests,
+            approve_pairing,
+            deny_pairing,
+            list_devices,
+            revoke_device,
+            get_action_history,
+            get_certificate_fingerprint,
+            rotate_certificate
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
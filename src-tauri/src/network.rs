@@ -0,0 +1,155 @@
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::config::ListenConfig;
+
+/// 設定画面に表示するネットワークインターフェース
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub addresses: Vec<IpAddr>,
+}
+
+/// ループバック以外のネットワークインターフェース一覧
+pub fn list_interfaces() -> Vec<NetworkInterface> {
+    let mut interfaces: Vec<NetworkInterface> = Vec::new();
+    for iface in if_addrs::get_if_addrs().unwrap_or_default() {
+        if iface.is_loopback() {
+            continue;
+        }
+        match interfaces.iter_mut().find(|i| i.name == iface.name) {
+            Some(existing) => existing.addresses.push(iface.ip()),
+            None => interfaces.push(NetworkInterface {
+                name: iface.name.clone(),
+                addresses: vec![iface.ip()],
+            }),
+        }
+    }
+    interfaces
+}
+
+/// IPv6のリンクローカルアドレス（スコープIDが必要なので待ち受けに使わない）
+fn is_link_local_v6(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(v6) if (v6.segments()[0] & 0xffc0) == 0xfe80)
+}
+
+/// 指定したインターフェースのアドレス
+fn interface_addresses(names: &[String], ipv6: bool) -> Vec<IpAddr> {
+    if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| names.contains(&iface.name))
+        .map(|iface| iface.ip())
+        .filter(|ip| ip.is_ipv4() || (ipv6 && !is_link_local_v6(ip)))
+        .collect()
+}
+
+/// ループバックアドレス（IPv4とIPv6）
+fn localhost_addresses() -> Vec<IpAddr> {
+    vec![
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ]
+}
+
+/// 指定したインターフェースのアドレス（1つもなければループバックアドレス）
+///
+/// 起動時にまだアドレスが付いていないと、どこでも待ち受けないままサーバーが動かなくなるので
+/// 設定画面からは繋がるようにしておく
+fn interface_addresses_or_localhost(names: &[String], ipv6: bool) -> Vec<IpAddr> {
+    let addresses = interface_addresses(names, ipv6);
+    if !addresses.is_empty() {
+        return addresses;
+    }
+    eprintln!(
+        "No address found on {}, listening on localhost only",
+        names.join(", ")
+    );
+    localhost_addresses()
+}
+
+/// 待ち受けるアドレス
+pub fn bind_addresses(listen: &ListenConfig, port: u16) -> Vec<SocketAddr> {
+    let ips = match listen {
+        ListenConfig::All { ipv6: false } => vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+        // デュアルスタックのソケット1つでIPv4も受ける
+        ListenConfig::All { ipv6: true } => vec![IpAddr::V6(Ipv6Addr::UNSPECIFIED)],
+        ListenConfig::Localhost => localhost_addresses(),
+        ListenConfig::Interfaces { names, ipv6 } => interface_addresses_or_localhost(names, *ipv6),
+        ListenConfig::Addresses { addresses } => addresses.clone(),
+    };
+    ips.into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect()
+}
+
+/// 待ち受け用のソケットを作る（`[::]` はIPv4も受けるデュアルスタックにする）
+pub fn bind_listener(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!addr.ip().is_unspecified())?;
+    }
+    // Windowsの SO_REUSEADDR は他のプロセスが同じポートを奪えてしまうので、Unixでだけ使う
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// QRコードやURLに使うアドレス
+pub fn advertised_ip(listen: &ListenConfig) -> Option<IpAddr> {
+    match listen {
+        ListenConfig::All { .. } => get_local_ip(),
+        ListenConfig::Localhost => Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        ListenConfig::Interfaces { names, ipv6 } => {
+            let addresses = interface_addresses_or_localhost(names, *ipv6);
+            // 同じネットワークの端末から使いやすいIPv4を優先
+            addresses
+                .iter()
+                .find(|ip| ip.is_ipv4())
+                .or(addresses.first())
+                .copied()
+        }
+        ListenConfig::Addresses { addresses } => addresses
+            .iter()
+            .find(|ip| !ip.is_unspecified())
+            .copied()
+            .or_else(get_local_ip),
+    }
+}
+
+/// ローカルIPアドレスを取得
+pub fn get_local_ip() -> Option<IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// URLのホスト部分（IPv6は角括弧で囲む）
+pub fn url_host(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => format!("[{}]", v6),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_interfaces_fall_back_to_localhost() {
+        let listen = ListenConfig::Interfaces {
+            names: vec!["no-such-interface".to_string()],
+            ipv6: true,
+        };
+        let addresses = bind_addresses(&listen, 52801);
+        assert!(!addresses.is_empty());
+        assert!(addresses.iter().all(|addr| addr.ip().is_loopback()));
+        assert!(advertised_ip(&listen).is_some_and(|ip| ip.is_loopback()));
+    }
+}
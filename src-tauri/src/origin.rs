@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+
+/// ブラウザから届いたリクエストの `Origin` を許可するか
+///
+/// 許可するのはこのサーバー自身のオリジン（スマホ用UIを開いたページ）と `allowed_origins` だけ。
+/// 自身のオリジンはホストがIPアドレスか `localhost` の場合に限る（DNSリバインディングで
+/// 別のドメイン名からこのサーバーに向けられたページを同一オリジンとみなさないように）
+pub fn is_allowed(
+    origin: &str,
+    host: Option<&str>,
+    https: bool,
+    allowed_origins: &[String],
+) -> bool {
+    let origin = origin.trim_end_matches('/');
+    if allowed_origins
+        .iter()
+        .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    {
+        return true;
+    }
+
+    let Some(host) = host else {
+        return false;
+    };
+    let scheme = if https { "https" } else { "http" };
+    is_address_host(host) && origin.eq_ignore_ascii_case(&format!("{}://{}", scheme, host))
+}
+
+/// `Host` ヘッダーがIPアドレスか `localhost` か（ポートは省略可）
+fn is_address_host(host: &str) -> bool {
+    // IPv6は `[::1]:52801` の形になる
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(rest, |(name, _)| name),
+        None => match host.rsplit_once(':') {
+            Some((name, port)) if port.parse::<u16>().is_ok() => name,
+            _ => host,
+        },
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "192.168.1.10:52801";
+
+    fn allowed_over_http(origin: &str, host: &str) -> bool {
+        is_allowed(origin, Some(host), false, &[])
+    }
+
+    #[test]
+    fn allows_own_origin_on_ip_or_localhost() {
+        assert!(allowed_over_http("http://192.168.1.10:52801", HOST));
+        assert!(allowed_over_http(
+            "http://localhost:52801",
+            "localhost:52801"
+        ));
+        assert!(allowed_over_http("http://[::1]:52801", "[::1]:52801"));
+        assert!(is_allowed(
+            "https://192.168.1.10:52801",
+            Some(HOST),
+            true,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn rejects_other_origins() {
+        assert!(!allowed_over_http("http://evil.example", HOST));
+        assert!(!allowed_over_http("http://192.168.1.10:8080", HOST));
+        assert!(!allowed_over_http("null", HOST));
+        // HTTPSで待ち受けているのにHTTPのページから
+        assert!(!is_allowed(
+            "http://192.168.1.10:52801",
+            Some(HOST),
+            true,
+            &[]
+        ));
+        assert!(!is_allowed("http://192.168.1.10:52801", None, false, &[]));
+    }
+
+    #[test]
+    fn rejects_dns_rebinding() {
+        // 攻撃者のドメインをこのPCのアドレスに向けると Host と Origin が一致してしまう
+        assert!(!allowed_over_http(
+            "http://rebind.evil.example:52801",
+            "rebind.evil.example:52801"
+        ));
+    }
+
+    #[test]
+    fn allows_configured_origins() {
+        let allowed = vec!["http://192.168.1.20:3000/".to_string()];
+        assert!(is_allowed(
+            "http://192.168.1.20:3000",
+            Some(HOST),
+            false,
+            &allowed
+        ));
+        assert!(is_allowed(
+            "HTTP://192.168.1.20:3000",
+            Some(HOST),
+            false,
+            &allowed
+        ));
+        assert!(!is_allowed(
+            "http://192.168.1.20:3001",
+            Some(HOST),
+            false,
+            &allowed
+        ));
+    }
+}
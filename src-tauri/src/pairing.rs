@@ -0,0 +1,312 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::ApiError;
+
+/// 承認待ちのペアリング要求の有効期間
+pub const PAIRING_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// 同時に承認待ちにできる要求の数（PCに確認が溢れないように）
+const MAX_PENDING: usize = 5;
+
+/// 端末名の最大文字数
+const MAX_NAME_LEN: usize = 40;
+
+/// PCに表示するペアリング要求
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingRequest {
+    /// スマホが結果を受け取るときに使うID（要求したスマホにだけ返す）
+    pub id: String,
+    /// スマホで入力した端末名
+    pub name: String,
+    /// 要求元のIPアドレス
+    pub address: String,
+    /// スマホとPCの両方に表示する確認コード（別の端末の要求を承認しないように）
+    pub code: String,
+}
+
+/// スマホに返すペアリングの状態
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PairingStatus {
+    Pending,
+    /// 承認された（端末のトークン）
+    Approved {
+        token: String,
+    },
+    Denied,
+    /// 期限切れ、または存在しない要求
+    Expired,
+}
+
+struct Pending {
+    request: PairingRequest,
+    ip: IpAddr,
+    status: PairingStatus,
+    expires_at: Instant,
+}
+
+impl Pending {
+    fn is_pending(&self) -> bool {
+        matches!(self.status, PairingStatus::Pending)
+    }
+}
+
+/// ペアリング要求（承認・拒否した要求もスマホが結果を受け取るか期限が切れるまで残す）
+#[derive(Default)]
+struct Requests {
+    entries: HashMap<String, Pending>,
+}
+
+impl Requests {
+    fn prune_expired(&mut self, now: Instant) {
+        self.entries.retain(|_, p| p.expires_at > now);
+    }
+
+    /// 要求を追加する（同じIPアドレスの承認待ちの要求を置き換えたら `true` も返す）
+    fn insert(
+        &mut self,
+        name: String,
+        ip: IpAddr,
+        now: Instant,
+    ) -> Result<(PairingRequest, bool), ApiError> {
+        self.prune_expired(now);
+        // 置き換えても期限は延ばさない（送り直し続けて居座れないように）
+        let replaced = self
+            .entries
+            .values()
+            .find(|p| p.ip == ip && p.is_pending())
+            .map(|p| p.expires_at);
+        self.entries.retain(|_, p| p.ip != ip || !p.is_pending());
+        // 承認・拒否済みでスマホが結果を受け取っていない要求は数えない
+        if replaced.is_none()
+            && self.entries.values().filter(|p| p.is_pending()).count() >= MAX_PENDING
+        {
+            return Err(ApiError::RateLimited {
+                message: "Too many pairing requests. Try again later".to_string(),
+                retry_after: None,
+            });
+        }
+
+        let request = PairingRequest {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            name,
+            address: ip.to_string(),
+            code: format!("{:06}", rand::random::<u32>() % 1_000_000),
+        };
+        self.entries.insert(
+            request.id.clone(),
+            Pending {
+                request: request.clone(),
+                ip,
+                status: PairingStatus::Pending,
+                expires_at: replaced.unwrap_or(now + PAIRING_TTL),
+            },
+        );
+        Ok((request, replaced.is_some()))
+    }
+
+    fn pending(&mut self, now: Instant) -> Vec<PairingRequest> {
+        self.prune_expired(now);
+        let mut requests: Vec<_> = self.entries.values().filter(|p| p.is_pending()).collect();
+        requests.sort_by_key(|p| p.expires_at);
+        requests.iter().map(|p| p.request.clone()).collect()
+    }
+
+    /// 承認待ちの要求を承認する（`add_device` で端末を登録してトークンを受け取る）
+    fn approve(
+        &mut self,
+        id: &str,
+        now: Instant,
+        add_device: impl FnOnce(&PairingRequest) -> Result<String, String>,
+    ) -> Result<(), String> {
+        self.prune_expired(now);
+        let Some(p) = self.entries.get_mut(id).filter(|p| p.is_pending()) else {
+            return Err("The pairing request has expired".to_string());
+        };
+        let token = add_device(&p.request)?;
+        p.status = PairingStatus::Approved { token };
+        Ok(())
+    }
+
+    fn deny(&mut self, id: &str) {
+        if let Some(p) = self.entries.get_mut(id) {
+            p.status = PairingStatus::Denied;
+        }
+    }
+
+    fn poll(&mut self, id: &str, now: Instant) -> PairingStatus {
+        self.prune_expired(now);
+        match self.entries.get(id).map(|p| p.status.clone()) {
+            Some(PairingStatus::Pending) => PairingStatus::Pending,
+            Some(status) => {
+                self.entries.remove(id);
+                status
+            }
+            None => PairingStatus::Expired,
+        }
+    }
+}
+
+static PENDING: once_cell::sync::Lazy<Mutex<Requests>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(Requests::default()));
+
+type RequestListener = Box<dyn Fn(&PairingRequest, bool) + Send + Sync>;
+
+/// ペアリング要求が届いたときに呼ぶ処理（設定画面への通知・トレイ表示）
+static REQUEST_LISTENER: once_cell::sync::OnceCell<RequestListener> =
+    once_cell::sync::OnceCell::new();
+
+/// ペアリング要求が届いたときの処理を登録
+///
+/// 2つ目の引数は同じIPアドレスの承認待ちの要求を置き換えたかどうか（置き換えならデスクトップに通知しない）
+pub fn on_request<F: Fn(&PairingRequest, bool) + Send + Sync + 'static>(listener: F) {
+    let _ = REQUEST_LISTENER.set(Box::new(listener));
+}
+
+/// スマホからのペアリング要求を受け付ける（同じIPアドレスの古い要求は置き換える）
+pub fn request(name: &str, ip: IpAddr) -> Result<PairingRequest, ApiError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "Enter a name for this device".to_string(),
+        ));
+    }
+    let name: String = name.chars().take(MAX_NAME_LEN).collect();
+
+    let (request, replaced) = PENDING.lock().unwrap().insert(name, ip, Instant::now())?;
+
+    if let Some(listener) = REQUEST_LISTENER.get() {
+        listener(&request, replaced);
+    }
+    Ok(request)
+}
+
+/// 承認待ちの要求（古い順）
+pub fn pending() -> Vec<PairingRequest> {
+    PENDING.lock().unwrap().pending(Instant::now())
+}
+
+/// 要求を承認して端末を登録する（トークンは次の問い合わせでスマホに渡す）
+pub fn approve(id: &str) -> Result<(), String> {
+    PENDING
+        .lock()
+        .unwrap()
+        .approve(id, Instant::now(), |request| {
+            crate::session::add_device(&request.name)
+        })
+}
+
+/// 要求を拒否する
+pub fn deny(id: &str) {
+    PENDING.lock().unwrap().deny(id);
+}
+
+/// スマホからの問い合わせに要求の状態を返す（結果を返した要求は消す）
+pub fn poll(id: &str) -> PairingStatus {
+    PENDING.lock().unwrap().poll(id, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, last))
+    }
+
+    fn request(requests: &mut Requests, last: u8, now: Instant) -> (PairingRequest, bool) {
+        requests
+            .insert(format!("Phone {}", last), ip(last), now)
+            .unwrap()
+    }
+
+    #[test]
+    fn approved_request_hands_over_the_token_once() {
+        let mut requests = Requests::default();
+        let now = Instant::now();
+        let (request, replaced) = request(&mut requests, 1, now);
+        assert!(!replaced);
+        assert_eq!(requests.poll(&request.id, now), PairingStatus::Pending);
+
+        requests
+            .approve(&request.id, now, |r| Ok(format!("token for {}", r.name)))
+            .unwrap();
+        assert!(requests.pending(now).is_empty());
+        // 承認済みの要求はもう一度承認できない
+        assert!(requests
+            .approve(&request.id, now, |_| unreachable!())
+            .is_err());
+
+        assert_eq!(
+            requests.poll(&request.id, now),
+            PairingStatus::Approved {
+                token: "token for Phone 1".to_string()
+            }
+        );
+        assert_eq!(requests.poll(&request.id, now), PairingStatus::Expired);
+    }
+
+    #[test]
+    fn denied_request_is_reported_once() {
+        let mut requests = Requests::default();
+        let now = Instant::now();
+        let (request, _) = request(&mut requests, 1, now);
+        requests.deny(&request.id);
+        assert!(requests.pending(now).is_empty());
+        assert_eq!(requests.poll(&request.id, now), PairingStatus::Denied);
+        assert_eq!(requests.poll(&request.id, now), PairingStatus::Expired);
+    }
+
+    #[test]
+    fn requests_expire() {
+        let mut requests = Requests::default();
+        let now = Instant::now();
+        let (request, _) = request(&mut requests, 1, now);
+        let later = now + PAIRING_TTL;
+        assert!(requests.pending(later).is_empty());
+        assert!(requests
+            .approve(&request.id, later, |_| unreachable!())
+            .is_err());
+        assert_eq!(requests.poll(&request.id, later), PairingStatus::Expired);
+    }
+
+    #[test]
+    fn same_address_replaces_the_pending_request() {
+        let mut requests = Requests::default();
+        let now = Instant::now();
+        let (first, _) = request(&mut requests, 1, now);
+        let (second, replaced) = request(&mut requests, 1, now + Duration::from_secs(60));
+        assert!(replaced);
+        assert_eq!(requests.poll(&first.id, now), PairingStatus::Expired);
+        assert_eq!(
+            requests
+                .pending(now)
+                .iter()
+                .map(|r| &r.id)
+                .collect::<Vec<_>>(),
+            [&second.id]
+        );
+        // 置き換えても最初の要求の期限で切れる
+        assert!(requests.pending(now + PAIRING_TTL).is_empty());
+    }
+
+    #[test]
+    fn unpolled_results_do_not_block_new_requests() {
+        let mut requests = Requests::default();
+        let now = Instant::now();
+        for last in 1..=MAX_PENDING as u8 {
+            request(&mut requests, last, now);
+        }
+        assert!(requests.insert("Phone".to_string(), ip(100), now).is_err());
+
+        let id = requests.pending(now)[0].id.clone();
+        requests
+            .approve(&id, now, |_| Ok("token".to_string()))
+            .unwrap();
+        assert!(requests.insert("Phone".to_string(), ip(100), now).is_ok());
+    }
+}
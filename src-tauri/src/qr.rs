@@ -1,14 +1,9 @@
 use base64::Engine;
 use image::{GrayImage, Luma};
 use qrcode::QrCode;
-use std::net::IpAddr;
 
-/// ローカルIPアドレスを取得
-pub fn get_local_ip() -> Option<IpAddr> {
-    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
-    socket.connect("8.8.8.8:80").ok()?;
-    socket.local_addr().ok().map(|addr| addr.ip())
-}
+use crate::config::AppConfig;
+use crate::network;
 
 /// QRコードをBase64エンコードされたPNG画像として生成
 pub fn generate_qr_code(url: &str) -> Result<String, String> {
@@ -64,21 +59,33 @@ pub fn generate_qr_code(url: &str) -> Result<String, String> {
     Ok(format!("data:image/png;base64,{}", base64_data))
 }
 
-/// サーバーURLを生成
-pub fn get_server_url(port: u16) -> String {
-    if let Some(ip) = get_local_ip() {
-        format!("http://{}:{}", ip, port)
+/// サーバーURLを生成（待ち受けアドレス・HTTPSの設定に合わせる）
+pub fn get_server_url(config: &AppConfig) -> String {
+    let scheme = if config.https { "https" } else { "http" };
+    if let Some(ip) = network::advertised_ip(&config.listen) {
+        format!("{}://{}:{}", scheme, network::url_host(ip), config.port)
     } else {
-        format!("http://localhost:{}", port)
+        format!("{}://localhost:{}", scheme, config.port)
     }
 }
 
-/// サーバーURL（PIN付き）を生成
-pub fn get_server_url_with_pin(port: u16, pin: &str) -> String {
-    let base_url = get_server_url(port);
-    if pin.is_empty() {
-        base_url
+/// QRコード用のサーバーURLを生成
+///
+/// 全体のPINがあれば使い捨てのログインチケットを、HTTPSなら証明書のフィンガープリントを付ける
+/// （スマホでブラウザの証明書の表示と見比べられるように）
+pub fn get_server_url_with_ticket(config: &AppConfig) -> Result<String, String> {
+    let mut params = Vec::new();
+    if !config.pin.is_empty() {
+        params.push(format!("ticket={}", crate::session::issue_ticket()));
+    }
+    if config.https {
+        params.push(format!("fp={}", crate::tls::fingerprint()?));
+    }
+
+    let base_url = get_server_url(config);
+    if params.is_empty() {
+        Ok(base_url)
     } else {
-        format!("{}?pin={}", base_url, pin)
+        Ok(format!("{}?{}", base_url, params.join("&")))
     }
 }
@@ -0,0 +1,305 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::AuthLimitConfig;
+
+/// 失敗の記録を忘れるまでの時間（最後の失敗から）
+const FORGET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 同じIPアドレスからペアリング要求を受け付ける間隔（要求のたびにPCに通知が出るので）
+const PAIRING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// ロックアウトが始まったときの通知内容
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    /// ロックアウトしたIPアドレス（全体のロックアウトなら `None`）
+    pub ip: Option<IpAddr>,
+    pub duration: Duration,
+}
+
+/// IPアドレスごとの失敗の記録
+struct IpState {
+    failures: u32,
+    last_failure: Instant,
+    /// 次の試行を受け付ける時刻
+    retry_at: Instant,
+    /// `retry_at` が待ち時間ではなくロックアウトによるものか
+    locked_out: bool,
+}
+
+#[derive(Default)]
+struct Limiter {
+    ips: HashMap<IpAddr, IpState>,
+    /// 全体の失敗時刻（古い順）
+    global_failures: VecDeque<Instant>,
+    global_locked_until: Option<Instant>,
+}
+
+static LIMITER: once_cell::sync::Lazy<Mutex<Limiter>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(Limiter::default()));
+
+impl Limiter {
+    fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let global = self.global_locked_until.filter(|until| *until > now);
+        let per_ip = self
+            .ips
+            .get(&ip)
+            .map(|state| state.retry_at)
+            .filter(|retry_at| *retry_at > now);
+        match global.into_iter().chain(per_ip).max() {
+            Some(until) => Err(until - now),
+            None => Ok(()),
+        }
+    }
+
+    /// 失敗を記録し、始まったロックアウトを返す
+    fn record_failure(
+        &mut self,
+        ip: IpAddr,
+        limits: &AuthLimitConfig,
+        now: Instant,
+    ) -> Vec<Lockout> {
+        let lockout = Duration::from_secs(limits.lockout_secs as u64);
+        let mut lockouts = Vec::new();
+
+        self.ips
+            .retain(|_, state| now.duration_since(state.last_failure) < FORGET_AFTER);
+
+        let state = self.ips.entry(ip).or_insert(IpState {
+            failures: 0,
+            last_failure: now,
+            retry_at: now,
+            locked_out: false,
+        });
+        state.failures = state.failures.saturating_add(1);
+        state.last_failure = now;
+        if limits.max_attempts_per_ip > 0 && state.failures >= limits.max_attempts_per_ip {
+            state.retry_at = now + lockout;
+            state.locked_out = true;
+            lockouts.push(Lockout {
+                ip: Some(ip),
+                duration: lockout,
+            });
+        } else {
+            // 失敗するたびに待ち時間を倍にする（ロックアウトの時間が上限）
+            let backoff = Duration::from_millis(limits.backoff_base_ms as u64)
+                .saturating_mul(2u32.saturating_pow(state.failures - 1));
+            state.retry_at = now + backoff.min(lockout);
+            state.locked_out = false;
+        }
+
+        if limits.max_attempts_global > 0 {
+            let window = Duration::from_secs(limits.global_window_secs as u64);
+            self.global_failures.push_back(now);
+            while self
+                .global_failures
+                .front()
+                .is_some_and(|at| now.duration_since(*at) >= window)
+            {
+                self.global_failures.pop_front();
+            }
+            if self.global_failures.len() >= limits.max_attempts_global as usize {
+                self.global_failures.clear();
+                self.global_locked_until = Some(now + lockout);
+                lockouts.push(Lockout {
+                    ip: None,
+                    duration: lockout,
+                });
+            }
+        }
+        lockouts
+    }
+
+    fn is_locked_out(&self, now: Instant) -> bool {
+        self.global_locked_until.is_some_and(|until| until > now)
+            || self
+                .ips
+                .values()
+                .any(|state| state.locked_out && state.retry_at > now)
+    }
+}
+
+/// IPアドレスごとの最後のペアリング要求の時刻
+static PAIRING_REQUESTS: once_cell::sync::Lazy<Mutex<HashMap<IpAddr, Instant>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 前の要求から `interval` が過ぎていれば受け付けて時刻を記録する（待つ必要があれば残り時間を返す）
+fn throttle(
+    last: &mut HashMap<IpAddr, Instant>,
+    ip: IpAddr,
+    interval: Duration,
+    now: Instant,
+) -> Result<(), Duration> {
+    last.retain(|_, at| now.duration_since(*at) < interval);
+    match last.get(&ip) {
+        Some(at) => Err(*at + interval - now),
+        None => {
+            last.insert(ip, now);
+            Ok(())
+        }
+    }
+}
+
+type LockoutListener = Box<dyn Fn(&Lockout) + Send + Sync>;
+
+/// ロックアウトが始まったときに呼ぶ処理（トレイ表示・デスクトップ通知）
+static LOCKOUT_LISTENER: once_cell::sync::OnceCell<LockoutListener> =
+    once_cell::sync::OnceCell::new();
+
+/// ロックアウトが始まったときの処理を登録
+pub fn on_lockout<F: Fn(&Lockout) + Send + Sync + 'static>(listener: F) {
+    let _ = LOCKOUT_LISTENER.set(Box::new(listener));
+}
+
+/// 認証を試してよいか（待つ必要があれば残り時間を返す）
+pub fn check(ip: IpAddr) -> Result<(), Duration> {
+    LIMITER.lock().unwrap().check(ip, Instant::now())
+}
+
+/// 認証の失敗を記録（ロックアウトが始まったら通知する）
+pub fn record_failure(ip: IpAddr, limits: &AuthLimitConfig) {
+    let lockouts = LIMITER
+        .lock()
+        .unwrap()
+        .record_failure(ip, limits, Instant::now());
+
+    for lockout in lockouts {
+        eprintln!(
+            "PIN authentication locked out for {}s ({})",
+            lockout.duration.as_secs(),
+            lockout
+                .ip
+                .map_or("all addresses".to_string(), |ip| ip.to_string())
+        );
+        if let Some(listener) = LOCKOUT_LISTENER.get() {
+            listener(&lockout);
+        }
+    }
+}
+
+/// 認証に成功したIPアドレスの失敗を忘れる
+pub fn record_success(ip: IpAddr) {
+    LIMITER.lock().unwrap().ips.remove(&ip);
+}
+
+/// ペアリング要求を受け付けてよいか（待つ必要があれば残り時間を返す）
+pub fn check_pairing(ip: IpAddr) -> Result<(), Duration> {
+    throttle(
+        &mut PAIRING_REQUESTS.lock().unwrap(),
+        ip,
+        PAIRING_INTERVAL,
+        Instant::now(),
+    )
+}
+
+/// ロックアウト中か（全体、またはいずれかのIPアドレス）
+pub fn is_locked_out() -> bool {
+    LIMITER.lock().unwrap().is_locked_out(Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> AuthLimitConfig {
+        AuthLimitConfig {
+            max_attempts_per_ip: 5,
+            max_attempts_global: 0,
+            global_window_secs: 60,
+            backoff_base_ms: 1000,
+            lockout_secs: 300,
+        }
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, last))
+    }
+
+    #[test]
+    fn backoff_doubles_after_each_failure() {
+        let mut limiter = Limiter::default();
+        let now = Instant::now();
+        for expected in [1, 2, 4, 8] {
+            assert!(limiter.record_failure(ip(1), &limits(), now).is_empty());
+            assert_eq!(
+                limiter.check(ip(1), now),
+                Err(Duration::from_secs(expected))
+            );
+        }
+        // 他のIPアドレスは待たされない
+        assert_eq!(limiter.check(ip(2), now), Ok(()));
+        assert_eq!(limiter.check(ip(1), now + Duration::from_secs(8)), Ok(()));
+        assert!(!limiter.is_locked_out(now));
+    }
+
+    #[test]
+    fn backoff_is_capped_by_the_lockout() {
+        let limits = AuthLimitConfig {
+            max_attempts_per_ip: 0,
+            lockout_secs: 3,
+            ..limits()
+        };
+        let mut limiter = Limiter::default();
+        let now = Instant::now();
+        for _ in 0..10 {
+            limiter.record_failure(ip(1), &limits, now);
+        }
+        assert_eq!(limiter.check(ip(1), now), Err(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn locks_out_an_ip_at_the_threshold() {
+        let mut limiter = Limiter::default();
+        let now = Instant::now();
+        for _ in 0..4 {
+            assert!(limiter.record_failure(ip(1), &limits(), now).is_empty());
+        }
+        let lockouts = limiter.record_failure(ip(1), &limits(), now);
+        assert_eq!(lockouts.len(), 1);
+        assert_eq!(lockouts[0].ip, Some(ip(1)));
+        assert_eq!(lockouts[0].duration, Duration::from_secs(300));
+        assert!(limiter.is_locked_out(now));
+        assert_eq!(limiter.check(ip(1), now), Err(Duration::from_secs(300)));
+        assert!(!limiter.is_locked_out(now + Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn locks_out_everyone_after_failures_within_the_window() {
+        let limits = AuthLimitConfig {
+            max_attempts_global: 3,
+            ..limits()
+        };
+        let mut limiter = Limiter::default();
+        let now = Instant::now();
+        assert!(limiter.record_failure(ip(1), &limits, now).is_empty());
+        assert!(limiter.record_failure(ip(2), &limits, now).is_empty());
+        // 期間が過ぎた失敗は数えない
+        let later = now + Duration::from_secs(60);
+        assert!(limiter.record_failure(ip(3), &limits, later).is_empty());
+        assert!(limiter.record_failure(ip(4), &limits, later).is_empty());
+
+        let lockouts = limiter.record_failure(ip(5), &limits, later);
+        assert_eq!(lockouts.len(), 1);
+        assert_eq!(lockouts[0].ip, None);
+        // 失敗していないIPアドレスもロックアウトされる
+        assert_eq!(limiter.check(ip(6), later), Err(Duration::from_secs(300)));
+        assert!(limiter.is_locked_out(later));
+    }
+
+    #[test]
+    fn pairing_requests_are_throttled_per_ip() {
+        let mut last = HashMap::new();
+        let now = Instant::now();
+        let interval = Duration::from_secs(10);
+        assert_eq!(throttle(&mut last, ip(1), interval, now), Ok(()));
+        assert_eq!(throttle(&mut last, ip(2), interval, now), Ok(()));
+        let soon = now + Duration::from_secs(4);
+        assert_eq!(
+            throttle(&mut last, ip(1), interval, soon),
+            Err(Duration::from_secs(6))
+        );
+        assert_eq!(throttle(&mut last, ip(1), interval, now + interval), Ok(()));
+    }
+}
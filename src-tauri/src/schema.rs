@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::AppConfig;
+
+/// スキーマファイル名（設定ディレクトリに書き出す）
+pub const SCHEMA_FILE_NAME: &str = "config.schema.json";
+
+/// 設定ファイルのJSON Schema（Rustの型から生成）
+pub fn config_schema() -> serde_json::Value {
+    schemars::schema_for!(AppConfig).to_value()
+}
+
+/// スキーマを設定ディレクトリ（または `path`）に書き出し、書き出したパスを返す
+pub fn write_schema_file(path: Option<PathBuf>) -> Result<PathBuf, String> {
+    let path = path.unwrap_or_else(|| AppConfig::config_dir().join(SCHEMA_FILE_NAME));
+    let content = serde_json::to_string_pretty(&config_schema()).map_err(|e| e.to_string())?;
+    fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// types.ts から `export interface Name { ... }` のフィールド名を取り出す
+    fn ts_interface_fields(source: &str, name: &str) -> Option<BTreeSet<String>> {
+        let start = source.find(&format!("export interface {} {{", name))?;
+        let body = &source[start..];
+        let body = &body[body.find('{')? + 1..body.find("\n}")?];
+        Some(
+            body.lines()
+                .filter_map(|line| {
+                    let field = line.trim().split(':').next()?;
+                    let field = field.trim_end_matches('?').trim_matches('\'');
+                    (!field.is_empty()).then(|| field.to_string())
+                })
+                .collect(),
+        )
+    }
+
+    fn schema_properties(schema: &serde_json::Value) -> BTreeSet<String> {
+        schema["properties"]
+            .as_object()
+            .map(|props| props.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn types_ts_matches_config_schema() {
+        let types_ts = fs::read_to_string(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../src/types.ts"),
+        )
+        .expect("src/types.ts should exist");
+        let schema = config_schema();
+
+        // $schema はエディタ用の参照なのでフロントエンドの型には含めない
+        let mut root = schema_properties(&schema);
+        root.remove("$schema");
+        assert_eq!(
+            ts_interface_fields(&types_ts, "AppConfig"),
+            Some(root),
+            "AppConfig in src/types.ts is out of sync"
+        );
+
+        let defs = schema["$defs"].as_object().expect("schema should have $defs");
+        for (name, def) in defs {
+            if let Some(variants) = def["oneOf"].as_array() {
+                // タグ付きenumは各variantのtypeがtypes.tsに存在するか確認
+                for variant in variants {
+                    let tag = variant["properties"]["type"]["const"]
+                        .as_str()
+                        .unwrap_or_else(|| panic!("{} variant should have a type tag", name));
+                    assert!(
+                        types_ts.contains(&format!("type: '{}'", tag)),
+                        "{} variant '{}' is missing from src/types.ts",
+                        name,
+                        tag
+                    );
+                }
+            } else if def["anyOf"].is_array() {
+                // タグなしenumは同名の型がtypes.tsにあるか確認
+                assert!(
+                    types_ts.contains(&format!("export type {} =", name)),
+                    "{} is missing from src/types.ts",
+                    name
+                );
+            } else if let Some(values) = def["enum"].as_array() {
+                // 文字列のenumは各値がtypes.tsの型に含まれるか確認
+                for value in values {
+                    let value = value.as_str().expect("enum values should be strings");
+                    assert!(
+                        types_ts.contains(&format!("'{}'", value)),
+                        "{} value '{}' is missing from src/types.ts",
+                        name,
+                        value
+                    );
+                }
+            } else {
+                assert_eq!(
+                    ts_interface_fields(&types_ts, name),
+                    Some(schema_properties(def)),
+                    "{} in src/types.ts is out of sync",
+                    name
+                );
+            }
+        }
+    }
+}
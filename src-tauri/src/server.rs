@@ -1,72 +1,286 @@
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        Json, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, FromRequest, FromRequestParts, Json, Path, Request, State,
     },
-    http::{header, Method, StatusCode},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
     response::{Html, IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 use futures::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
-use tokio::sync::broadcast;
-use tower_http::cors::{Any, CorsLayer};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
-use crate::config::{AppConfig, ButtonConfig};
+use crate::audit::{AuditEntry, AuditQuery, Transport};
+use crate::auth::Principal;
+use crate::bundle::{ConflictStrategy, ImportPreview, LayoutBundle};
+use crate::config::{
+    AppConfig, ButtonConfig, ConfigFormat, ConfigLoadError, PageConfig, ThemeConfig,
+};
+use crate::diff::PageDiff;
+use crate::error::ApiError;
 
 /// キャッシュされた設定
 static CONFIG_CACHE: once_cell::sync::Lazy<RwLock<AppConfig>> =
-    once_cell::sync::Lazy::new(|| RwLock::new(AppConfig::load()));
+    once_cell::sync::Lazy::new(|| {
+        let config = AppConfig::load().map(migrate_pins).unwrap_or_else(|e| {
+            eprintln!("Failed to load config: {}", e);
+            set_load_error(Some(ConfigLoadError {
+                using_defaults: true,
+                ..e
+            }));
+            AppConfig::default()
+        });
+        RwLock::new(config)
+    });
+
+/// 設定のリビジョン（キャッシュが変わるたびに増やす）
+///
+/// 起動時刻のミリ秒から始めるので、再起動前のリビジョンを持ったクライアントと重ならない
+static CONFIG_REVISION: once_cell::sync::Lazy<AtomicU64> = once_cell::sync::Lazy::new(|| {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    AtomicU64::new(millis)
+});
+
+/// 再接続したクライアントに差分を送るために残す過去の設定の数
+const CONFIG_HISTORY_LEN: usize = 16;
+
+/// 過去の設定（古い順、リビジョン付き）
+static CONFIG_HISTORY: once_cell::sync::Lazy<RwLock<VecDeque<(u64, AppConfig)>>> =
+    once_cell::sync::Lazy::new(|| RwLock::new(VecDeque::new()));
+
+/// 直近の設定読み込みエラー（解決されるまで設定ファイルへの書き込みを拒否する）
+static LOAD_ERROR: once_cell::sync::Lazy<RwLock<Option<ConfigLoadError>>> =
+    once_cell::sync::Lazy::new(|| RwLock::new(None));
 
 /// キャッシュから設定を取得
 pub fn get_cached_config() -> AppConfig {
     CONFIG_CACHE.read().unwrap().clone()
 }
 
-/// キャッシュを更新
-fn reload_config() {
-    if let Ok(mut cache) = CONFIG_CACHE.write() {
-        *cache = AppConfig::load();
+/// キャッシュされた設定とそのリビジョン
+fn cached_config_with_revision() -> (AppConfig, u64) {
+    let cache = CONFIG_CACHE.read().unwrap();
+    (cache.clone(), CONFIG_REVISION.load(Ordering::SeqCst))
+}
+
+/// 過去のリビジョンの設定（履歴から消えていれば `None`）
+fn config_at_revision(revision: u64) -> Option<AppConfig> {
+    CONFIG_HISTORY
+        .read()
+        .unwrap()
+        .iter()
+        .find(|(r, _)| *r == revision)
+        .map(|(_, config)| config.clone())
+}
+
+/// キャッシュを置き換えてリビジョンを進める（内容が同じなら何もせず `false`）
+fn replace_cached_config(config: AppConfig) -> bool {
+    let mut cache = CONFIG_CACHE.write().unwrap();
+    if *cache == config {
+        return false;
+    }
+    // PINが変わった利用者は認証し直す
+    if let Err(e) = crate::session::revoke_changed(&cache, &config) {
+        eprintln!("Failed to revoke sessions: {}", e);
     }
+    let old = std::mem::replace(&mut *cache, config);
+    let revision = CONFIG_REVISION.fetch_add(1, Ordering::SeqCst);
+
+    let mut history = CONFIG_HISTORY.write().unwrap();
+    history.push_back((revision, old));
+    if history.len() > CONFIG_HISTORY_LEN {
+        history.pop_front();
+    }
+    true
 }
 
-use crate::keyboard;
+/// 設定読み込みエラーを取得
+pub fn get_load_error() -> Option<ConfigLoadError> {
+    // キャッシュ初期化時にエラーが記録されるので先に初期化しておく
+    once_cell::sync::Lazy::force(&CONFIG_CACHE);
+    LOAD_ERROR.read().unwrap().clone()
+}
+
+fn set_load_error(error: Option<ConfigLoadError>) {
+    if let Ok(mut load_error) = LOAD_ERROR.write() {
+        *load_error = error;
+    }
+}
+
+/// 再読み込みの失敗を記録（キャッシュは変えないので、デフォルト設定かどうかは引き継ぐ）
+fn record_reload_error(error: &ConfigLoadError) {
+    if let Ok(mut load_error) = LOAD_ERROR.write() {
+        let using_defaults = load_error.as_ref().is_some_and(|e| e.using_defaults);
+        *load_error = Some(ConfigLoadError {
+            using_defaults,
+            ..error.clone()
+        });
+    }
+}
+
+/// 起動時に設定ファイルを読み込めずデフォルト設定で動いていれば認証させない
+///
+/// デフォルト設定にはPINもユーザーもないので、そのまま認証すると誰でも所有者として
+/// 操作できてしまう。一度読み込めた設定を使い続けている場合は通常どおり応答する
+fn ensure_config_loaded() -> Result<(), ApiError> {
+    match get_load_error() {
+        Some(error) if error.using_defaults => Err(ApiError::ConfigBroken),
+        _ => Ok(()),
+    }
+}
+
+/// 読み込みエラーを破棄し、現在のキャッシュ（最後に読み込めた設定かデフォルト設定）での上書きを許可
+pub fn discard_load_error() {
+    set_load_error(None);
+}
+
+/// 平文のPINが残っていればハッシュにして書き戻す
+///
+/// 平文のPINを含むバックアップを増やさないよう、バックアップは残さない
+fn migrate_pins(mut config: AppConfig) -> AppConfig {
+    match crate::auth::hash_plaintext_pins(&mut config) {
+        Ok(true) => {
+            if let Err(e) = config.save_without_backup() {
+                eprintln!("Failed to save hashed PINs: {}", e);
+            }
+        }
+        Ok(false) => {}
+        Err(e) => eprintln!("Failed to hash PINs: {}", e),
+    }
+    config
+}
+
+/// キャッシュを更新（読み込みに失敗した場合は現在の設定を維持）
+///
+/// 内容が変わった場合は `Ok(true)` を返す
+fn reload_config() -> Result<bool, ConfigLoadError> {
+    let config = AppConfig::load()
+        .map(migrate_pins)
+        .inspect_err(record_reload_error)?;
+    set_load_error(None);
+    Ok(replace_cached_config(config))
+}
 
 /// WebSocket経由で送信するメッセージの種類
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum WsMessage {
-    /// 設定が更新された
+    /// 設定が更新された（差分で表せないので `/api/config` から読み直す）
     ConfigUpdated,
-    /// Ping（接続確認）
+    /// 設定の差分（`Subscribe` したクライアントにだけ送る）
+    ConfigPatch(ConfigPatch),
+    /// 差分の購読（クライアントから送る）
+    Subscribe(Subscribe),
+    /// Ping（クライアントからの接続確認）
     Ping,
-    /// Pong（接続確認応答）
+    /// Pong（Pingを送った接続にだけ返す）
     Pong,
+    /// ボタンのアクション実行（クライアントから送る）
+    Execute(Execute),
+    /// アクションの実行結果（`Execute` を送った接続にだけ返す）
+    ExecuteAck(ExecuteAck),
+    /// 動的ラベルが変化した（ボタンID → ラベル）
+    LabelsUpdated(std::collections::HashMap<String, String>),
+}
+
+/// 設定の差分（`base_revision` の設定に `pages` を適用すると `revision` の設定になる）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigPatch {
+    base_revision: u64,
+    revision: u64,
+    pages: Vec<PageDiff>,
+}
+
+/// 差分の購読要求（`revision` はクライアントが持っている設定のリビジョン）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscribe {
+    #[serde(default)]
+    revision: Option<u64>,
+}
+
+/// WebSocketでのアクション実行要求（`id` はクライアントが振る番号で、結果にそのまま付けて返す）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Execute {
+    id: u64,
+    button_id: String,
+}
+
+/// `Execute` の結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecuteAck {
+    id: u64,
+    success: bool,
+    /// 失敗した場合のエラーコード（HTTPのエラーレスポンスの `code` と同じ）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    message: String,
+    /// 要求を受け取ってから実行し終わるまでの時間（ミリ秒）
+    elapsed_ms: u64,
 }
 
 /// アプリケーション状態（WebSocket broadcast用）
 #[derive(Clone)]
 pub struct AppState {
     pub tx: broadcast::Sender<WsMessage>,
+    /// HTTPSで待ち受けているか（自身のオリジンのスキーム）
+    pub https: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AuthRequest {
+    #[serde(default)]
     pin: String,
+    /// QRコードに埋め込んだ使い捨てのログインチケット（PINの代わり）
+    #[serde(default)]
+    ticket: Option<String>,
+}
+
+/// `/api/auth` の結果（セッショントークン）
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    success: bool,
+    message: String,
+    token: String,
+    /// トークンの有効期限（UNIX時間の秒）
+    expires_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairRequest {
+    /// 設定画面に表示する端末名
+    name: String,
 }
 
+/// `/api/pair` の結果（状態の問い合わせに使うIDと確認コード）
 #[derive(Debug, Serialize)]
-pub struct AuthResponse {
+pub struct PairResponse {
     success: bool,
     message: String,
+    request_id: String,
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairStatusRequest {
+    request_id: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ActionRequest {
     button_id: String,
-    pin: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -77,105 +291,641 @@ pub struct ActionResponse {
 
 #[derive(Debug, Serialize)]
 pub struct ConfigResponse {
+    /// 設定のリビジョン（WebSocketの差分はこのリビジョンを基準にする）
+    revision: u64,
     grid: crate::config::GridConfig,
+    landscape_grid: Option<crate::config::GridConfig>,
     buttons: Vec<ButtonConfig>,
+    pages: Vec<PageConfig>,
+    theme: ThemeConfig,
+    /// 認証したユーザー名（全体のPINなら null）
+    user: Option<String>,
 }
 
-/// PIN認証
-async fn auth(Json(req): Json<AuthRequest>) -> Json<AuthResponse> {
-    let config = get_cached_config();
+#[derive(Debug, Deserialize)]
+pub struct BundleExportRequest {
+    /// 省略時は全ページ
+    #[serde(default)]
+    page_id: Option<String>,
+}
 
-    if config.pin.is_empty() || req.pin == config.pin {
-        Json(AuthResponse {
-            success: true,
-            message: "Authentication successful".to_string(),
-        })
-    } else {
-        Json(AuthResponse {
-            success: false,
-            message: "Invalid PIN".to_string(),
-        })
+#[derive(Debug, Deserialize)]
+pub struct BundleImportRequest {
+    bundle: LayoutBundle,
+    #[serde(default)]
+    strategy: ConflictStrategy,
+    /// trueならプレビューのみ（設定は変更しない）
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// JSONの本文（読めなければ axum の既定の平文ではなく `invalid_request` を返す）
+struct JsonBody<T>(T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for JsonBody<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        Json::<T>::from_request(request, state)
+            .await
+            .map(|Json(value)| JsonBody(value))
+            .map_err(|e| ApiError::InvalidRequest(e.body_text()))
     }
 }
 
-/// ボタン設定を取得
-async fn get_config(Json(req): Json<AuthRequest>) -> Response {
+/// `Authorization: Bearer <token>` のトークン
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// セッショントークンで認証した利用者（トークンが無効なら401を返す）
+struct Authenticated(Principal);
+
+impl<S: Send + Sync> FromRequestParts<S> for Authenticated {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        bearer_token(&parts.headers)
+            .and_then(|token| crate::session::verify(&get_cached_config(), token))
+            .map(Authenticated)
+            .ok_or(ApiError::InvalidSession)
+    }
+}
+
+/// PIN認証（成功したらセッショントークンを発行）
+///
+/// 失敗が続いたIPアドレスは待ち時間が過ぎるまでPINを確認せずに429を返す
+async fn auth(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    JsonBody(req): JsonBody<AuthRequest>,
+) -> Result<Json<SessionResponse>, ApiError> {
+    ensure_config_loaded()?;
     let config = get_cached_config();
+    // デュアルスタックのソケットではIPv4がIPv4射影アドレスで届く
+    let ip = addr.ip().to_canonical();
 
-    // PIN検証
-    if !config.pin.is_empty() && req.pin != config.pin {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(AuthResponse {
-                success: false,
-                message: "Invalid PIN".to_string(),
-            }),
-        )
-            .into_response();
+    if let Err(retry_after) = crate::ratelimit::check(ip) {
+        let seconds = retry_after.as_secs_f64().ceil() as u64;
+        return Err(ApiError::RateLimited {
+            message: format!("Too many attempts. Try again in {} seconds", seconds),
+            retry_after: Some(seconds),
+        });
     }
 
-    Json(ConfigResponse {
-        grid: config.grid.clone(),
-        buttons: config.buttons.clone(),
+    // ハッシュの計算で非同期のスレッドを止めないよう別スレッドで確認する
+    let limits = config.auth_limits.clone();
+    // PINを入力していない（ページを開いたときのPINなしで入れるかの確認）なら失敗に数えない
+    let guessed = req.ticket.is_some() || !req.pin.is_empty();
+    let principal = tokio::task::spawn_blocking(move || match &req.ticket {
+        Some(ticket) => crate::session::redeem_ticket(ticket).then_some(Principal::Owner),
+        None => Principal::authenticate(&config, &req.pin),
     })
-    .into_response()
+    .await
+    .ok()
+    .flatten();
+
+    let Some(principal) = principal else {
+        if guessed {
+            crate::ratelimit::record_failure(ip, &limits);
+        }
+        return Err(ApiError::InvalidCredentials);
+    };
+    crate::ratelimit::record_success(ip);
+
+    let (token, expires_at) = crate::session::issue(&principal)
+        .map_err(|e| ApiError::Internal(format!("Failed to create session: {}", e)))?;
+    Ok(Json(SessionResponse {
+        success: true,
+        message: "Authentication successful".to_string(),
+        token,
+        expires_at,
+    }))
 }
 
-/// ボタンアクションを実行
-async fn execute_action(Json(req): Json<ActionRequest>) -> Json<ActionResponse> {
-    let config = get_cached_config();
+/// 自分のセッションを失効させる
+async fn logout(headers: HeaderMap) -> Response {
+    if let Some(token) = bearer_token(&headers) {
+        if let Err(e) = crate::session::revoke(token) {
+            eprintln!("Failed to revoke session: {}", e);
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
 
-    // PIN検証
-    if !config.pin.is_empty() && req.pin != config.pin {
-        return Json(ActionResponse {
-            success: false,
-            message: "Invalid PIN".to_string(),
+/// ペアリング要求（PCで承認されると、状態の問い合わせで端末のトークンを受け取れる）
+async fn pair(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    JsonBody(req): JsonBody<PairRequest>,
+) -> Result<Json<PairResponse>, ApiError> {
+    let ip = addr.ip().to_canonical();
+    if let Err(retry_after) = crate::ratelimit::check_pairing(ip) {
+        let seconds = retry_after.as_secs_f64().ceil() as u64;
+        return Err(ApiError::RateLimited {
+            message: format!(
+                "Too many pairing requests. Try again in {} seconds",
+                seconds
+            ),
+            retry_after: Some(seconds),
         });
     }
+    let request = crate::pairing::request(&req.name, ip)?;
+    Ok(Json(PairResponse {
+        success: true,
+        message: "Approve this device on your computer".to_string(),
+        request_id: request.id,
+        code: request.code,
+    }))
+}
 
-    // ボタンを探す
-    let button = config.buttons.iter().find(|b| b.id == req.button_id);
+/// ペアリング要求の状態
+async fn pair_status(
+    JsonBody(req): JsonBody<PairStatusRequest>,
+) -> Json<crate::pairing::PairingStatus> {
+    Json(crate::pairing::poll(&req.request_id))
+}
 
-    match button {
-        Some(btn) => {
-            let action = btn.action.clone();
+/// 利用者に見せる設定（使えるページ・ボタンだけ、メインページが先頭）
+#[derive(Debug, Clone, PartialEq)]
+struct ClientView {
+    pages: Vec<PageConfig>,
+    theme: ThemeConfig,
+    user: Option<String>,
+}
 
-            match keyboard::execute_action(&action) {
-                Ok(()) => Json(ActionResponse {
-                    success: true,
-                    message: "Action executed".to_string(),
-                }),
-                Err(e) => Json(ActionResponse {
-                    success: false,
-                    message: format!("Action failed: {}", e),
-                }),
-            }
+impl ClientView {
+    /// 使えるボタンがひとつもなければ、ボタンのないメインページだけになる
+    fn new(config: &AppConfig, principal: &Principal) -> Self {
+        Self {
+            pages: principal.visible_pages(config),
+            theme: config.theme.clone(),
+            user: principal.name().map(str::to_string),
+        }
+    }
+
+    /// 差分（テーマやページ構成が変わった場合は `None`）
+    fn diff(&self, new: &ClientView) -> Option<Vec<PageDiff>> {
+        if self.theme != new.theme || self.user != new.user {
+            return None;
+        }
+        crate::diff::diff_pages(&self.pages, &new.pages)
+    }
+
+    fn into_response(self, revision: u64) -> ConfigResponse {
+        let mut pages = self.pages.into_iter();
+        let main = pages
+            .next()
+            .expect("visible_pages always includes the main page");
+        ConfigResponse {
+            revision,
+            grid: main.grid,
+            landscape_grid: main.landscape_grid,
+            buttons: main.buttons,
+            pages: pages.collect(),
+            theme: self.theme,
+            user: self.user,
+        }
+    }
+}
+
+/// ボタン設定を取得
+async fn get_config(Authenticated(principal): Authenticated) -> Response {
+    let (config, revision) = cached_config_with_revision();
+
+    // 利用者が使えるページ・ボタンだけを返す
+    Json(ClientView::new(&config, &principal).into_response(revision)).into_response()
+}
+
+/// ボタンアクションを実行
+///
+/// 認証できなかった試行も操作履歴に残すので、`Authenticated` ではなくここでトークンを確認する
+async fn execute_action(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    JsonBody(req): JsonBody<ActionRequest>,
+) -> Result<Json<ActionResponse>, ApiError> {
+    execute_blocking(
+        bearer_token(&headers).map(str::to_string),
+        addr.ip().to_canonical(),
+        req.button_id,
+        Transport::Http,
+    )
+    .await?;
+    Ok(Json(ActionResponse {
+        success: true,
+        message: "Action executed".to_string(),
+    }))
+}
+
+/// `execute_and_record` を別スレッドで実行する（HTTPとWebSocketで共通）
+///
+/// キー入力と操作履歴の書き込みで非同期のスレッドを止めないようにする
+async fn execute_blocking(
+    token: Option<String>,
+    ip: IpAddr,
+    button_id: String,
+    transport: Transport,
+) -> Result<(), ApiError> {
+    tokio::task::spawn_blocking(move || {
+        execute_and_record(token.as_deref(), ip, &button_id, transport)
+    })
+    .await
+    .unwrap_or_else(|e| Err(ApiError::Internal(format!("Action failed: {}", e))))
+}
+
+/// トークンを確認してボタンのアクションを実行し、操作履歴に残す
+fn execute_and_record(
+    token: Option<&str>,
+    ip: IpAddr,
+    button_id: &str,
+    transport: Transport,
+) -> Result<(), ApiError> {
+    let started = std::time::Instant::now();
+    let principal = token.and_then(|token| crate::session::verify(&get_cached_config(), token));
+    let result = match (ensure_config_loaded(), &principal) {
+        (Err(e), _) => Err(e),
+        (Ok(()), Some(principal)) => run_button(principal, button_id),
+        (Ok(()), None) => Err(ApiError::InvalidSession),
+    };
+
+    crate::audit::record(AuditEntry {
+        timestamp: crate::audit::now_millis(),
+        device: token
+            .filter(|_| principal.is_some())
+            .and_then(crate::session::device_name),
+        user: principal
+            .as_ref()
+            .and_then(Principal::name)
+            .map(str::to_string),
+        session: token.map(crate::session::short_id).unwrap_or_default(),
+        ip: ip.to_string(),
+        button_id: button_id.to_string(),
+        transport,
+        success: result.is_ok(),
+        code: result.as_ref().err().map(|e| e.code().to_string()),
+        error: result.as_ref().err().map(ApiError::to_string),
+        latency_ms: started.elapsed().as_millis() as u64,
+    });
+    result
+}
+
+/// 利用者が押したボタンのアクションを実行（HTTPとWebSocketで共通）
+fn run_button(principal: &Principal, button_id: &str) -> Result<(), ApiError> {
+    let config = get_cached_config();
+
+    // ボタンを探す（許可されていないボタンは、IDを探られないよう存在しないボタンと同じエラーにする）
+    let Some(button) = principal.find_button(&config, button_id) else {
+        return Err(ApiError::ButtonNotFound(button_id.to_string()));
+    };
+    Ok(crate::actions::execute(&button.action)?)
+}
+
+/// WebSocketで届いた `Execute` を実行して結果を返す
+///
+/// キー入力の順番が入れ替わらないよう、接続ごとに届いた順に1つずつ実行する
+async fn execute_over_ws(token: &str, ip: IpAddr, request: Execute) -> ExecuteAck {
+    let started = std::time::Instant::now();
+    let result = execute_blocking(
+        Some(token.to_string()),
+        ip,
+        request.button_id,
+        Transport::WebSocket,
+    )
+    .await;
+    let (success, code, message) = match result {
+        Ok(()) => (true, None, "Action executed".to_string()),
+        Err(e) => (false, Some(e.code().to_string()), e.to_string()),
+    };
+    ExecuteAck {
+        id: request.id,
+        success,
+        code,
+        message,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+/// レイアウトをバンドルとしてエクスポート
+async fn export_bundle(
+    Authenticated(principal): Authenticated,
+    JsonBody(req): JsonBody<BundleExportRequest>,
+) -> Result<Json<LayoutBundle>, ApiError> {
+    let config = get_cached_config();
+
+    // 制限付きのユーザーは使えるページ・ボタンだけをエクスポートできる
+    let mut visible = config.clone();
+    if !principal.has_full_access() {
+        let mut pages = principal.visible_pages(&config).into_iter();
+        if let Some(main) = pages.next() {
+            visible.buttons = main.buttons;
         }
-        None => Json(ActionResponse {
-            success: false,
-            message: format!("Button not found: {}", req.button_id),
-        }),
+        visible.pages = pages.collect();
+    }
+
+    LayoutBundle::export(&visible, req.page_id.as_deref())
+        .map(Json)
+        .map_err(ApiError::NotFound)
+}
+
+/// バンドルをインポート（dry_run ならプレビューのみ）
+async fn import_bundle(
+    Authenticated(principal): Authenticated,
+    JsonBody(req): JsonBody<BundleImportRequest>,
+) -> Result<Json<ImportPreview>, ApiError> {
+    let config = get_cached_config();
+
+    // 設定の変更は全ボタンを使える利用者だけ
+    if !principal.has_full_access() {
+        return Err(ApiError::Forbidden(
+            "Importing layouts is not allowed for this user".to_string(),
+        ));
+    }
+
+    let result: Result<ImportPreview, String> = if req.dry_run {
+        req.bundle
+            .plan_import(&config, req.strategy)
+            .map(|(_, preview)| preview)
+    } else {
+        apply_bundle(&req.bundle, req.strategy)
+    };
+
+    result.map(Json).map_err(ApiError::InvalidRequest)
+}
+
+/// 操作履歴（新しい順）
+async fn history(
+    Authenticated(principal): Authenticated,
+    JsonBody(query): JsonBody<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    // 他の利用者の操作も見えるので、全ボタンを使える利用者だけ
+    if !principal.has_full_access() {
+        return Err(ApiError::Forbidden(
+            "Viewing the action history is not allowed for this user".to_string(),
+        ));
+    }
+    Ok(Json(crate::audit::query(&query)))
+}
+
+/// 設定ファイルのJSON Schema
+async fn serve_schema() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/schema+json")],
+        Json(crate::schema::config_schema()),
+    )
+}
+
+/// アップロードされたアイコン画像（保存名が内容のハッシュなので長期キャッシュ可）
+async fn serve_asset(Path(name): Path<String>, headers: HeaderMap) -> Response {
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|etag| etag.trim_matches('"') == name)
+    {
+        return StatusCode::NOT_MODIFIED.into_response();
+    }
+
+    match crate::assets::read_asset(&name) {
+        Some(data) => (
+            [
+                (header::CONTENT_TYPE, crate::assets::content_type(&name).to_string()),
+                (header::CACHE_CONTROL, "public, max-age=31536000, immutable".to_string()),
+                (header::ETAG, format!("\"{}\"", name)),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+                // SVG内のスクリプトは実行させない
+                (
+                    header::CONTENT_SECURITY_POLICY,
+                    "default-src 'none'; style-src 'unsafe-inline'".to_string(),
+                ),
+            ],
+            data,
+        )
+            .into_response(),
+        None => ApiError::NotFound(format!("Asset not found: {}", name)).into_response(),
     }
 }
 
-/// WebSocket接続ハンドラ
+/// 組み込みアイコン
+async fn serve_builtin_icon(Path(name): Path<String>) -> Response {
+    let name = name.trim_end_matches(".svg");
+    match crate::assets::builtin_icon_svg(name) {
+        Some(svg) => (
+            [
+                (header::CONTENT_TYPE, "image/svg+xml"),
+                (header::CACHE_CONTROL, "public, max-age=86400"),
+            ],
+            svg,
+        )
+            .into_response(),
+        None => ApiError::NotFound(format!("Icon not found: {}", name)).into_response(),
+    }
+}
+
+/// WebSocketのサブプロトコル名
+const WS_PROTOCOL: &str = "tapkey";
+
+/// セッションが無効・失効して切断するときのクローズコード（スマホは再接続せずに認証画面に戻る）
+const WS_CLOSE_REVOKED: u16 = 4001;
+
+/// サーバーからWebSocketのPingフレームを送る間隔
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+
+/// この間クライアントから何も届かなければ（Pingフレームへの応答もなければ）切断する
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// WebSocket接続ハンドラ（`Origin` は `check_origin` で確認済み）
+///
+/// ブラウザのWebSocketはヘッダーを付けられないので、トークンは
+/// `Sec-WebSocket-Protocol: tapkey, <token>` で受け取る
 async fn ws_handler(
     ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+) -> Response {
+    if let Err(e) = ensure_config_loaded() {
+        return e.into_response();
+    }
+    let token = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').map(str::trim).find(|p| *p != WS_PROTOCOL))
+        .map(str::to_string);
+    let token = token.filter(|token| crate::session::verify(&get_cached_config(), token).is_some());
+
+    ws.protocols([WS_PROTOCOL])
+        .on_upgrade(move |socket| async move {
+            match token {
+                Some(token) => handle_socket(socket, state, token, addr.ip().to_canonical()).await,
+                // 接続を拒否するとブラウザには理由が伝わらない（1006で閉じたことしか分からない）ので、
+                // 接続してからクローズコードで伝える
+                None => close_invalid_session(socket).await,
+            }
+        })
+}
+
+/// トークンが無効な接続を閉じる
+async fn close_invalid_session(mut socket: WebSocket) {
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: WS_CLOSE_REVOKED,
+            reason: ApiError::InvalidSession.to_string().into(),
+        })))
+        .await;
+}
+
+/// 差分を購読している接続の状態
+struct Subscription {
+    token: String,
+    revision: u64,
+    view: ClientView,
+}
+
+impl Subscription {
+    /// 購読を始める（クライアントの設定が古ければ追いつくためのメッセージも返す）
+    fn start(token: &str, request: Subscribe) -> (Option<Self>, Option<WsMessage>) {
+        let (config, revision) = cached_config_with_revision();
+        let Some(principal) = crate::session::verify(&config, token) else {
+            // 読み直させて認証画面に戻す
+            return (None, Some(WsMessage::ConfigUpdated));
+        };
+        let view = ClientView::new(&config, &principal);
+
+        let message = match request.revision {
+            None => None,
+            Some(r) if r == revision => None,
+            Some(r) => {
+                let old = config_at_revision(r).map(|old| ClientView::new(&old, &principal));
+                match old.and_then(|old| old.diff(&view)) {
+                    Some(pages) => Some(WsMessage::ConfigPatch(ConfigPatch {
+                        base_revision: r,
+                        revision,
+                        pages,
+                    })),
+                    // 履歴に無いほど古い
+                    None => Some(WsMessage::ConfigUpdated),
+                }
+            }
+        };
+
+        let subscription = Self {
+            token: token.to_string(),
+            revision,
+            view,
+        };
+        (Some(subscription), message)
+    }
+
+    /// 設定の更新をこの接続向けのメッセージにする（`None` なら購読を終える）
+    fn update(mut self) -> (Option<Self>, Option<WsMessage>) {
+        let (config, revision) = cached_config_with_revision();
+        if revision == self.revision {
+            return (Some(self), None);
+        }
+        // セッションが失効していれば読み直させて認証画面に戻す
+        let Some(principal) = crate::session::verify(&config, &self.token) else {
+            return (None, Some(WsMessage::ConfigUpdated));
+        };
+        let view = ClientView::new(&config, &principal);
+
+        let message = match self.view.diff(&view) {
+            Some(pages) => WsMessage::ConfigPatch(ConfigPatch {
+                base_revision: self.revision,
+                revision,
+                pages,
+            }),
+            None => WsMessage::ConfigUpdated,
+        };
+        self.revision = revision;
+        self.view = view;
+        (Some(self), Some(message))
+    }
+}
+
+async fn send_message(
+    sender: &mut futures::stream::SplitSink<WebSocket, Message>,
+    msg: &WsMessage,
+) -> Result<(), axum::Error> {
+    match serde_json::to_string(msg) {
+        Ok(json) => sender.send(Message::Text(json.into())).await,
+        Err(_) => Ok(()),
+    }
 }
 
 /// WebSocket接続を処理
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>, token: String, ip: IpAddr) {
     let (mut sender, mut receiver) = socket.split();
     let mut rx = state.tx.subscribe();
+    let mut revoked_rx = crate::session::subscribe_revoked();
+    // 受信タスクから送信タスクへクライアントの要求と実行結果を渡す（応答はbroadcastではなくこの接続にだけ送る）
+    let (request_tx, mut request_rx) = mpsc::channel::<WsMessage>(4);
+    let recv_token = token.clone();
 
-    // broadcast受信タスク
+    // broadcast受信タスク（最初に現在の動的ラベルを送る）
     let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            if let Ok(json) = serde_json::to_string(&msg) {
-                if sender.send(Message::Text(json.into())).await.is_err() {
+        let labels = crate::labels::current_labels();
+        if !labels.is_empty()
+            && send_message(&mut sender, &WsMessage::LabelsUpdated(labels))
+                .await
+                .is_err()
+        {
+            return;
+        }
+
+        let mut subscription: Option<Subscription> = None;
+        let start = tokio::time::Instant::now() + HEARTBEAT_INTERVAL;
+        let mut heartbeat = tokio::time::interval_at(start, HEARTBEAT_INTERVAL);
+        loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => match msg {
+                    // 購読中の接続には設定の更新を差分にして送る
+                    Ok(WsMessage::ConfigUpdated) => match subscription.take() {
+                        Some(current) => {
+                            let (next, msg) = current.update();
+                            subscription = next;
+                            msg
+                        }
+                        None => Some(WsMessage::ConfigUpdated),
+                    },
+                    Ok(msg) => Some(msg),
+                    Err(_) => break,
+                },
+                request = request_rx.recv() => match request {
+                    Some(WsMessage::Ping) => Some(WsMessage::Pong),
+                    Some(ack @ WsMessage::ExecuteAck(_)) => Some(ack),
+                    Some(WsMessage::Subscribe(request)) => {
+                        let (next, msg) = Subscription::start(&token, request);
+                        subscription = next;
+                        msg
+                    }
+                    Some(_) => None,
+                    None => break,
+                },
+                _ = heartbeat.tick() => {
+                    if sender.send(Message::Ping(Default::default())).await.is_err() {
+                        break;
+                    }
+                    None
+                }
+                // この接続のセッション（端末）が失効したらすぐに切断する
+                _ = revoked_rx.recv() => {
+                    if crate::session::verify(&get_cached_config(), &token).is_none() {
+                        let _ = sender
+                            .send(Message::Close(Some(CloseFrame {
+                                code: WS_CLOSE_REVOKED,
+                                reason: "Session revoked".into(),
+                            })))
+                            .await;
+                        break;
+                    }
+                    None
+                }
+            };
+
+            if let Some(msg) = msg {
+                if send_message(&mut sender, &msg).await.is_err() {
                     break;
                 }
             }
@@ -183,16 +933,22 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     });
 
     // クライアントからのメッセージ受信タスク
-    let tx = state.tx.clone();
     let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
-            if let Message::Text(text) = msg {
-                // Pingに応答
-                if let Ok(ws_msg) = serde_json::from_str::<WsMessage>(&text) {
-                    if matches!(ws_msg, WsMessage::Ping) {
-                        let _ = tx.send(WsMessage::Pong);
-                    }
+        // ハートビートにも応答しない（通信が途絶えた）接続は終了する
+        while let Ok(Some(Ok(msg))) = tokio::time::timeout(HEARTBEAT_TIMEOUT, receiver.next()).await
+        {
+            let Message::Text(text) = msg else {
+                continue;
+            };
+            let request = match serde_json::from_str::<WsMessage>(&text) {
+                Ok(WsMessage::Execute(request)) => {
+                    WsMessage::ExecuteAck(execute_over_ws(&recv_token, ip, request).await)
                 }
+                Ok(request @ (WsMessage::Ping | WsMessage::Subscribe(_))) => request,
+                _ => continue,
+            };
+            if request_tx.send(request).await.is_err() {
+                break;
             }
         }
     });
@@ -265,11 +1021,39 @@ async fn serve_icon_512() -> impl IntoResponse {
     )
 }
 
+/// `Origin` が自身のオリジンか `allowed_origins` に含まれるか
+fn origin_allowed(origin: &HeaderValue, headers: &HeaderMap, https: bool) -> bool {
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    origin.to_str().is_ok_and(|origin| {
+        crate::origin::is_allowed(origin, host, https, &get_cached_config().allowed_origins)
+    })
+}
+
+/// 許可していないオリジンのページからのリクエストを拒否する（`/ws` の接続も含む）
+///
+/// CORSはブラウザが応答を読めなくするだけで、キー操作の実行などのリクエスト自体は届いてしまうので
+/// 処理する前に403を返す（`Origin` のないブラウザ以外からのリクエストはそのまま通す）
+async fn check_origin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(origin) = request.headers().get(header::ORIGIN) {
+        if !origin_allowed(origin, request.headers(), state.https) {
+            return ApiError::OriginNotAllowed.into_response();
+        }
+    }
+    next.run(request).await
+}
+
 pub fn create_router(state: Arc<AppState>) -> Router {
+    let https = state.https;
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(AllowOrigin::predicate(move |origin, parts| {
+            origin_allowed(origin, &parts.headers, https)
+        }))
         .allow_methods([Method::GET, Method::POST])
-        .allow_headers(Any);
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]);
 
     Router::new()
         .route("/", get(serve_ui))
@@ -280,10 +1064,20 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         .route("/manifest.json", get(serve_manifest))
         .route("/icon-192.png", get(serve_icon_192))
         .route("/icon-512.png", get(serve_icon_512))
+        .route("/assets/{name}", get(serve_asset))
+        .route("/icons/{name}", get(serve_builtin_icon))
+        .route("/api/schema", get(serve_schema))
         .route("/api/auth", post(auth))
+        .route("/api/logout", post(logout))
+        .route("/api/pair", post(pair))
+        .route("/api/pair/status", post(pair_status))
         .route("/api/config", post(get_config))
         .route("/api/action", post(execute_action))
+        .route("/api/bundle/export", post(export_bundle))
+        .route("/api/bundle/import", post(import_bundle))
+        .route("/api/history", post(history))
         .route("/ws", get(ws_handler))
+        .layer(middleware::from_fn_with_state(state.clone(), check_origin))
         .layer(cors)
         .with_state(state)
 }
@@ -292,28 +1086,498 @@ pub fn create_router(state: Arc<AppState>) -> Router {
 static BROADCAST_TX: once_cell::sync::OnceCell<broadcast::Sender<WsMessage>> =
     once_cell::sync::OnceCell::new();
 
-/// 設定更新を全クライアントに通知（キャッシュも更新）
-pub fn notify_config_updated() {
-    reload_config();
+/// 読み込みに失敗した設定ファイルはユーザーが対処するまで上書きしない
+fn ensure_writable() -> Result<(), String> {
+    match get_load_error() {
+        Some(error) => Err(format!(
+            "Config file could not be loaded, not overwriting it: {}",
+            error
+        )),
+        None => Ok(()),
+    }
+}
+
+/// 設定を検証して保存し、全クライアントに通知
+pub fn update_config(config: &AppConfig) -> Result<(), String> {
+    ensure_writable()?;
+    config.validate()?;
+    // 設定画面で入力された平文のPINはハッシュにしてから保存する
+    let mut config = config.clone();
+    crate::auth::hash_plaintext_pins(&mut config)?;
+    config.save().map_err(|e| e.to_string())?;
+    notify_config_updated().map_err(|e| e.to_string())
+}
+
+/// バックアップから設定を復元し、全クライアントに通知（復元前の設定もバックアップされる）
+///
+/// 平文のPINが残った古いバックアップも、設定画面からの保存と同じくPINの重複を確認してハッシュにする
+pub fn restore_backup(name: &str) -> Result<(), String> {
+    ensure_writable()?;
+    let config = AppConfig::read_backup(name).map_err(|e| e.to_string())?;
+    update_config(&config)
+}
+
+/// 設定ファイルを別の形式に変換し、変換後のパスを返す
+pub fn convert_config_format(format: ConfigFormat) -> Result<std::path::PathBuf, String> {
+    ensure_writable()?;
+    get_cached_config()
+        .convert_to(format)
+        .map_err(|e| e.to_string())
+}
+
+/// バンドルを現在の設定に取り込んで保存
+pub fn apply_bundle(
+    bundle: &LayoutBundle,
+    strategy: ConflictStrategy,
+) -> Result<ImportPreview, String> {
+    let (config, preview) = bundle.plan_import(&get_cached_config(), strategy)?;
+    ensure_writable()?;
+    bundle.store_assets()?;
+    update_config(&config)?;
+    Ok(preview)
+}
+
+/// 外部で編集された設定ファイルを反映（無効な内容なら現在の設定を維持する）
+///
+/// 反映した場合は `Ok(true)`、内容が変わっていない場合は `Ok(false)` を返す
+pub fn apply_external_change() -> Result<bool, ConfigLoadError> {
+    let config = AppConfig::read_from(&AppConfig::config_path())
+        .map(migrate_pins)
+        .inspect_err(record_reload_error)?;
+    set_load_error(None);
+
+    if !replace_cached_config(config) {
+        return Ok(false);
+    }
+
     if let Some(tx) = BROADCAST_TX.get() {
         let _ = tx.send(WsMessage::ConfigUpdated);
     }
+    Ok(true)
 }
 
-pub async fn start_server(port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// 設定更新を全クライアントに通知（キャッシュも更新、内容が変わっていなければ通知しない）
+pub fn notify_config_updated() -> Result<(), ConfigLoadError> {
+    if !reload_config()? {
+        return Ok(());
+    }
+    if let Some(tx) = BROADCAST_TX.get() {
+        let _ = tx.send(WsMessage::ConfigUpdated);
+    }
+    Ok(())
+}
+
+pub async fn start_server(
+    listen: crate::config::ListenConfig,
+    port: u16,
+    https: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let (tx, _rx) = broadcast::channel::<WsMessage>(100);
 
     // グローバルにsenderを保存
     let _ = BROADCAST_TX.set(tx.clone());
 
-    let state = Arc::new(AppState { tx });
+    // 動的ラベルの評価
+    let labels_tx = tx.clone();
+    tokio::spawn(crate::labels::run(move |labels| {
+        let _ = labels_tx.send(WsMessage::LabelsUpdated(labels));
+    }));
+
+    let state = Arc::new(AppState { tx, https });
     let app = create_router(state);
 
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
-    println!("HTTP Server listening on http://{}", addr);
+    // HTTPSなら自己署名証明書を読み込む（なければ生成）
+    let tls = if https {
+        Some(crate::tls::listener_config()?)
+    } else {
+        None
+    };
+    let scheme = if https { "https" } else { "http" };
+
+    // 設定されたアドレスごとに待ち受ける（一部のアドレスが使えなくても他は続行）
+    let mut servers = Vec::new();
+    for addr in crate::network::bind_addresses(&listen, port) {
+        match crate::network::bind_listener(addr) {
+            Ok(listener) => {
+                println!("HTTP Server listening on {}://{}", scheme, addr);
+                let app = app.clone();
+                // PIN認証の回数制限に接続元のアドレスを使う
+                let service = app.into_make_service_with_connect_info::<SocketAddr>();
+                let tls = tls.clone();
+                servers.push(tokio::spawn(async move {
+                    match tls {
+                        Some(tls) => {
+                            axum_server::from_tcp_rustls(listener, tls)
+                                .serve(service)
+                                .await
+                        }
+                        None => {
+                            let listener = tokio::net::TcpListener::from_std(listener)?;
+                            axum::serve(listener, service).await
+                        }
+                    }
+                }));
+            }
+            Err(e) => eprintln!("Failed to listen on {}: {}", addr, e),
+        }
+    }
+    if servers.is_empty() {
+        return Err("No address to listen on".into());
+    }
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    for server in servers {
+        server.await??;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TestConfigDir;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    const HOST: &str = "192.168.1.10:52801";
+
+    /// リクエスト元のスマホのアドレス
+    const PHONE: SocketAddr =
+        SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 20)), 50000);
+
+    /// 設定は実際の設定ディレクトリではなく一時ディレクトリに読み書きする（テストの終わりに削除）
+    fn router() -> (Router, TestConfigDir) {
+        let config_dir = TestConfigDir::new();
+        let (tx, _rx) = broadcast::channel(1);
+        (
+            create_router(Arc::new(AppState { tx, https: false })),
+            config_dir,
+        )
+    }
+
+    fn request(method: Method, uri: &str, host: &str, origin: Option<&str>) -> Request {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::HOST, host)
+            .header(header::CONTENT_TYPE, "application/json")
+            .extension(ConnectInfo(PHONE));
+        if let Some(origin) = origin {
+            builder = builder.header(header::ORIGIN, origin);
+        }
+        builder
+            .body(Body::from(r#"{"button_id":"compact"}"#))
+            .unwrap()
+    }
+
+    fn preflight(origin: &str) -> Request {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/action")
+            .header(header::HOST, HOST)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "authorization,content-type",
+            )
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_cross_origin_action() {
+        let (app, _config_dir) = router();
+        let response = app
+            .oneshot(request(
+                Method::POST,
+                "/api/action",
+                HOST,
+                Some("http://evil.example"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_cross_origin_websocket_upgrade() {
+        let (app, _config_dir) = router();
+        let request = Request::builder()
+            .uri("/ws")
+            .header(header::HOST, HOST)
+            .header(header::ORIGIN, "http://evil.example")
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .header(header::SEC_WEBSOCKET_PROTOCOL, "tapkey, token")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_dns_rebinding_origin() {
+        let (app, _config_dir) = router();
+        let host = "rebind.evil.example:52801";
+        let response = app
+            .oneshot(request(
+                Method::POST,
+                "/api/action",
+                host,
+                Some("http://rebind.evil.example:52801"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn cross_origin_preflight_gets_no_cors_headers() {
+        let (app, _config_dir) = router();
+        let response = app.oneshot(preflight("http://evil.example")).await.unwrap();
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn same_origin_preflight_is_allowed() {
+        let (app, _config_dir) = router();
+        let origin = "http://192.168.1.10:52801";
+        let response = app.oneshot(preflight(origin)).await.unwrap();
+        assert_eq!(
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(&HeaderValue::from_static(origin))
+        );
+    }
+
+    #[tokio::test]
+    async fn same_origin_and_non_browser_requests_reach_the_handler() {
+        let (app, _config_dir) = router();
+        // トークンがないので認証で401になる（オリジンの確認では止まらない）
+        for origin in [Some("http://192.168.1.10:52801"), None] {
+            let response = app
+                .clone()
+                .oneshot(request(Method::POST, "/api/action", HOST, origin))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    async fn error_code(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        body["code"].as_str().unwrap().to_string()
+    }
+
+    fn authorized(uri: &str, token: &str, body: &str) -> Request {
+        Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(header::HOST, HOST)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .extension(ConnectInfo(PHONE))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn errors_have_status_and_code() {
+        let (app, _config_dir) = router();
+        let (token, _) = crate::session::issue(&Principal::Owner).unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(request(Method::POST, "/api/action", HOST, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(error_code(response).await, "invalid_session");
+
+        let response = app
+            .clone()
+            .oneshot(authorized(
+                "/api/action",
+                &token,
+                r#"{"button_id":"missing"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_code(response).await, "button_not_found");
+
+        let response = app
+            .oneshot(authorized("/api/action", &token, "{"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_code(response).await, "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn broken_config_is_not_served_as_the_default() {
+        let (app, _config_dir) = router();
+        let (token, _) = crate::session::issue(&Principal::Owner).unwrap();
+        set_load_error(Some(ConfigLoadError {
+            path: "config.json".into(),
+            message: "expected value".to_string(),
+            line: None,
+            column: None,
+            backup_path: None,
+            using_defaults: true,
+        }));
+
+        let auth = app
+            .clone()
+            .oneshot(authorized("/api/auth", "", r#"{"pin":""}"#))
+            .await
+            .unwrap();
+        let action = app
+            .oneshot(authorized(
+                "/api/action",
+                &token,
+                r#"{"button_id":"missing"}"#,
+            ))
+            .await
+            .unwrap();
+        discard_load_error();
+
+        assert_eq!(auth.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error_code(auth).await, "config_broken");
+        assert_eq!(action.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error_code(action).await, "config_broken");
+    }
+
+    #[tokio::test]
+    async fn invalid_external_edit_keeps_serving_the_last_config() {
+        let (app, _config_dir) = router();
+        let mut config = AppConfig::default();
+        config.buttons.pop();
+        config.save().unwrap();
+        assert!(apply_external_change().unwrap());
+
+        std::fs::write(AppConfig::config_path(), "{").unwrap();
+        assert!(apply_external_change().is_err());
+        let error = get_load_error();
+        let cached = get_cached_config();
+
+        let auth = app
+            .oneshot(authorized("/api/auth", "", r#"{"pin":""}"#))
+            .await
+            .unwrap();
+        discard_load_error();
+        replace_cached_config(AppConfig::default());
+
+        assert!(error.is_some_and(|e| !e.using_defaults));
+        assert_eq!(cached, config);
+        assert_eq!(auth.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn restored_backups_are_checked_for_duplicate_pins() {
+        let config_dir = TestConfigDir::new();
+        let user = crate::config::UserConfig {
+            name: "guest".to_string(),
+            pin: "1111".to_string(),
+            pages: None,
+            buttons: None,
+        };
+        let config = AppConfig {
+            pin: "1111".to_string(),
+            users: vec![user],
+            ..AppConfig::default()
+        };
+        let backups = config_dir.path().join("backups");
+        std::fs::create_dir_all(&backups).unwrap();
+        std::fs::write(
+            backups.join("config-1.json"),
+            serde_json::to_string(&config).unwrap(),
+        )
+        .unwrap();
+
+        let error = restore_backup("config-1.json").unwrap_err();
+        assert!(error.contains("same as the PIN"), "{}", error);
+        assert!(!AppConfig::config_path().exists());
+    }
+
+    #[test]
+    fn hidden_buttons_look_like_unknown_buttons() {
+        let _config_dir = TestConfigDir::new();
+        let config = get_cached_config();
+        let hidden = &config.buttons[0].id;
+        let user = Principal::User(crate::config::UserConfig {
+            name: "guest".to_string(),
+            pin: "1234".to_string(),
+            pages: Some(Vec::new()),
+            buttons: None,
+        });
+
+        assert_eq!(
+            run_button(&user, hidden),
+            Err(ApiError::ButtonNotFound(hidden.clone()))
+        );
+        assert_eq!(
+            run_button(&user, "missing"),
+            Err(ApiError::ButtonNotFound("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn user_without_allowed_buttons_sees_no_buttons() {
+        let config = AppConfig::default();
+        assert!(!config.buttons.is_empty());
+        let user = Principal::User(crate::config::UserConfig {
+            name: "guest".to_string(),
+            pin: "1234".to_string(),
+            pages: Some(Vec::new()),
+            buttons: None,
+        });
+
+        let response = ClientView::new(&config, &user).into_response(1);
+        assert!(response.buttons.is_empty());
+        assert!(response.pages.is_empty());
+        assert_eq!(response.user.as_deref(), Some("guest"));
+    }
+
+    #[tokio::test]
+    async fn action_attempts_are_recorded_in_history() {
+        let (app, _config_dir) = router();
+        let (token, _) = crate::session::issue(&Principal::Owner).unwrap();
+        let body = r#"{"button_id":"missing"}"#;
+        app.clone()
+            .oneshot(authorized("/api/action", &token, body))
+            .await
+            .unwrap();
+        app.clone()
+            .oneshot(authorized("/api/action", "invalid.token", body))
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(authorized("/api/history", &token, body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let entries: Vec<AuditEntry> = serde_json::from_slice(&body).unwrap();
+
+        // 新しい順
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].code.as_deref(), Some("invalid_session"));
+        assert_eq!(entries[1].code.as_deref(), Some("button_not_found"));
+        assert_eq!(entries[1].session, crate::session::short_id(&token));
+        assert_eq!(entries[1].ip, "192.168.1.20");
+        assert_eq!(entries[1].transport, Transport::Http);
+    }
+}
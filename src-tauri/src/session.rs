@@ -0,0 +1,531 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use crate::auth::Principal;
+use crate::config::{restrict_to_owner, write_private, AppConfig};
+
+/// セッションを保存するファイル（設定ファイルと同じディレクトリ）
+///
+/// 再起動のたびにスマホでPINを入れ直さずに済むよう、署名鍵と発行済みのセッションを保存する
+const SESSIONS_FILE_NAME: &str = "sessions.json";
+
+/// セッションの有効期間
+const SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// QRコードに埋め込むログインチケットの有効期間
+const TICKET_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// 端末の最終アクセス日時を保存し直す間隔（リクエストのたびに書き込まないように）
+const LAST_SEEN_INTERVAL: u64 = 60;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 発行済みのセッション
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Session {
+    /// 認証したユーザー名（全体のPINなら `None`）
+    user: Option<String>,
+    /// 有効期限（UNIX時間の秒）
+    expires_at: u64,
+}
+
+/// ペアリングで承認した端末（期限なしで全ボタンを使える）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedDevice {
+    /// トークンのID部分（設定画面から削除するときに指定する）
+    pub id: String,
+    /// スマホで入力した端末名
+    pub name: String,
+    /// 承認した日時（UNIX時間の秒）
+    pub paired_at: u64,
+    /// 最後にアクセスした日時（UNIX時間の秒）
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionStore {
+    /// トークンの署名鍵
+    secret: String,
+    /// セッションID → セッション
+    sessions: HashMap<String, Session>,
+    /// 承認済みの端末
+    #[serde(default)]
+    devices: Vec<TrustedDevice>,
+}
+
+impl SessionStore {
+    fn new() -> Self {
+        Self {
+            secret: hex::encode(rand::random::<[u8; 32]>()),
+            sessions: HashMap::new(),
+            devices: Vec::new(),
+        }
+    }
+
+    fn sign(&self, id: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(id.as_bytes());
+        mac
+    }
+
+    fn token(&self, id: &str) -> String {
+        let signature = hex::encode(self.sign(id).finalize().into_bytes());
+        format!("{}.{}", id, signature)
+    }
+
+    fn prune_expired(&mut self) {
+        let now = unix_now();
+        self.sessions.retain(|_, session| session.expires_at > now);
+    }
+}
+
+/// 保存済みのセッション（初回アクセス時に読み込む）
+static STORE: once_cell::sync::Lazy<RwLock<SessionStore>> =
+    once_cell::sync::Lazy::new(|| RwLock::new(load()));
+
+/// 未使用のログインチケット → 有効期限（再起動で消えてよいので保存しない）
+static TICKETS: once_cell::sync::Lazy<Mutex<HashMap<String, Instant>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// セッションや端末を失効させたことの通知（接続中のWebSocketを切るため）
+static REVOKED: once_cell::sync::Lazy<broadcast::Sender<()>> =
+    once_cell::sync::Lazy::new(|| broadcast::channel(16).0);
+
+fn sessions_path() -> PathBuf {
+    AppConfig::config_dir().join(SESSIONS_FILE_NAME)
+}
+
+fn load() -> SessionStore {
+    let path = sessions_path();
+    // 以前のバージョンが誰でも読める権限で保存したファイルも絞る
+    if path.exists() {
+        if let Err(e) = restrict_to_owner(&path) {
+            eprintln!("Failed to restrict access to the sessions file: {}", e);
+        }
+    }
+    let mut store = fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<SessionStore>(&content).ok())
+        .filter(|store| !store.secret.is_empty())
+        .unwrap_or_else(SessionStore::new);
+    store.prune_expired();
+    store
+}
+
+fn save(store: &SessionStore) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(store).map_err(|e| e.to_string())?;
+    // 署名鍵があればトークンを作れるので、他のユーザーから読めないようにする
+    write_private(&sessions_path(), content.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// 利用者のセッションを発行し、トークンと有効期限（UNIX時間の秒）を返す
+pub fn issue(principal: &Principal) -> Result<(String, u64), String> {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let expires_at = unix_now() + SESSION_TTL.as_secs();
+
+    let mut store = STORE.write().unwrap();
+    store.prune_expired();
+    store.sessions.insert(
+        id.clone(),
+        Session {
+            user: principal.name().map(str::to_string),
+            expires_at,
+        },
+    );
+    save(&store)?;
+
+    Ok((store.token(&id), expires_at))
+}
+
+/// トークンから利用者を特定（署名が合わない・期限切れ・失効済みなら `None`）
+pub fn verify(config: &AppConfig, token: &str) -> Option<Principal> {
+    let (id, signature) = token.split_once('.')?;
+    let signature = hex::decode(signature).ok()?;
+
+    let store = STORE.read().unwrap();
+    store.sign(id).verify_slice(&signature).ok()?;
+    if let Some(device) = store.devices.iter().find(|device| device.id == id) {
+        if unix_now() >= device.last_seen + LAST_SEEN_INTERVAL {
+            drop(store);
+            touch_device(id);
+        }
+        return Some(Principal::Owner);
+    }
+    let session = store.sessions.get(id)?;
+    if session.expires_at <= unix_now() {
+        return None;
+    }
+
+    match &session.user {
+        None => Some(Principal::Owner),
+        Some(name) => config
+            .users
+            .iter()
+            .find(|user| &user.name == name)
+            .map(|user| Principal::User(user.clone())),
+    }
+}
+
+/// トークンのセッションID（署名を除いた部分）の先頭（操作履歴で端末を見分けるのに使う）
+pub fn short_id(token: &str) -> String {
+    let id = token.split_once('.').map_or(token, |(id, _)| id);
+    id.chars().take(8).collect()
+}
+
+/// ペアリングした端末のトークンなら端末名（`verify` で確認済みのトークンに使う）
+pub fn device_name(token: &str) -> Option<String> {
+    let (id, _) = token.split_once('.')?;
+    STORE
+        .read()
+        .unwrap()
+        .devices
+        .iter()
+        .find(|device| device.id == id)
+        .map(|device| device.name.clone())
+}
+
+/// 端末の最終アクセス日時を更新
+///
+/// `verify` は非同期の処理から呼ばれるので、ファイルへの書き込みは `spawn_blocking` で行う
+fn touch_device(id: &str) {
+    let mut store = STORE.write().unwrap();
+    let now = unix_now();
+    let Some(device) = store.devices.iter_mut().find(|device| device.id == id) else {
+        return;
+    };
+    // 同時に届いたリクエストが揃って書き込まないよう、ロックを取ってから確認し直す
+    if now < device.last_seen + LAST_SEEN_INTERVAL {
+        return;
+    }
+    device.last_seen = now;
+    drop(store);
+
+    let persist = || {
+        if let Err(e) = save(&STORE.read().unwrap()) {
+            eprintln!("Failed to save device access time: {}", e);
+        }
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => drop(handle.spawn_blocking(persist)),
+        Err(_) => persist(),
+    }
+}
+
+/// ペアリングを承認した端末を登録し、その端末のトークンを返す
+pub fn add_device(name: &str) -> Result<String, String> {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let now = unix_now();
+
+    let mut store = STORE.write().unwrap();
+    store.devices.push(TrustedDevice {
+        id: id.clone(),
+        name: name.to_string(),
+        paired_at: now,
+        last_seen: now,
+    });
+    save(&store)?;
+    Ok(store.token(&id))
+}
+
+/// 承認済みの端末（承認した順）
+pub fn list_devices() -> Vec<TrustedDevice> {
+    STORE.read().unwrap().devices.clone()
+}
+
+/// 端末の承認を取り消す（接続中のWebSocketもすぐに切断する）
+pub fn revoke_device(id: &str) -> Result<(), String> {
+    let mut store = STORE.write().unwrap();
+    let count = store.devices.len();
+    store.devices.retain(|device| device.id != id);
+    if store.devices.len() == count {
+        return Err(format!("Device not found: {}", id));
+    }
+    save(&store)?;
+    notify_revoked();
+    Ok(())
+}
+
+/// セッションや端末を失効させたときの通知を受け取る
+pub fn subscribe_revoked() -> broadcast::Receiver<()> {
+    REVOKED.subscribe()
+}
+
+fn notify_revoked() {
+    let _ = REVOKED.send(());
+}
+
+/// 全体のPINの代わりに一度だけ使えるログインチケットを発行
+///
+/// QRコードには平文のPINを埋め込めない（保存しているのはハッシュだけ）ので、
+/// 読み取ったスマホはこのチケットでセッションを受け取る
+pub fn issue_ticket() -> String {
+    let ticket = uuid::Uuid::new_v4().simple().to_string();
+    let now = Instant::now();
+    let mut tickets = TICKETS.lock().unwrap();
+    tickets.retain(|_, expires_at| *expires_at > now);
+    tickets.insert(ticket.clone(), now + TICKET_TTL);
+    ticket
+}
+
+/// ログインチケットを使う（有効なら `true`、使ったチケットは無効になる）
+pub fn redeem_ticket(ticket: &str) -> bool {
+    TICKETS
+        .lock()
+        .unwrap()
+        .remove(ticket)
+        .is_some_and(|expires_at| expires_at > Instant::now())
+}
+
+/// トークンのセッションを失効させる
+pub fn revoke(token: &str) -> Result<(), String> {
+    let Some((id, _)) = token.split_once('.') else {
+        return Ok(());
+    };
+    let mut store = STORE.write().unwrap();
+    if store.sessions.remove(id).is_some() {
+        save(&store)?;
+        notify_revoked();
+    }
+    Ok(())
+}
+
+/// 全セッションと承認済みの端末を失効させる
+pub fn revoke_all() -> Result<(), String> {
+    let mut store = STORE.write().unwrap();
+    store.sessions.clear();
+    store.devices.clear();
+    save(&store)?;
+    notify_revoked();
+    Ok(())
+}
+
+/// PINが変わった（またはユーザーが削除された）利用者のセッションを失効させる
+///
+/// ペアリングした端末も所有者として操作できるので、全体のPINが変わったら承認を取り消す
+pub fn revoke_changed(old: &AppConfig, new: &AppConfig) -> Result<(), String> {
+    let user_pin = |config: &AppConfig, name: &str| {
+        config
+            .users
+            .iter()
+            .find(|user| user.name == name)
+            .map(|user| user.pin.clone())
+    };
+    let still_valid = |session: &Session| match &session.user {
+        None => old.pin == new.pin,
+        Some(name) => user_pin(old, name).is_some() && user_pin(old, name) == user_pin(new, name),
+    };
+
+    // 全体のPINが変わったら未使用のログインチケットも無効にする
+    if old.pin != new.pin {
+        TICKETS.lock().unwrap().clear();
+    }
+
+    let mut store = STORE.write().unwrap();
+    let count = store.sessions.len() + store.devices.len();
+    store.sessions.retain(|_, session| still_valid(session));
+    if old.pin != new.pin {
+        store.devices.clear();
+    }
+    if store.sessions.len() + store.devices.len() != count {
+        save(&store)?;
+        notify_revoked();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TestConfigDir, UserConfig};
+
+    fn config_with_user(pin: &str) -> AppConfig {
+        AppConfig {
+            pin: "owner-pin".to_string(),
+            users: vec![UserConfig {
+                name: "guest".to_string(),
+                pin: pin.to_string(),
+                pages: None,
+                buttons: None,
+            }],
+            ..AppConfig::default()
+        }
+    }
+
+    fn guest(config: &AppConfig) -> Principal {
+        Principal::User(config.users[0].clone())
+    }
+
+    #[test]
+    fn issued_tokens_verify_only_with_their_signature() {
+        let _config_dir = TestConfigDir::new();
+        let config = config_with_user("1234");
+        let (token, expires_at) = issue(&Principal::Owner).unwrap();
+        assert!(expires_at > unix_now());
+        assert!(matches!(verify(&config, &token), Some(Principal::Owner)));
+
+        let (id, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", id, "0".repeat(signature.len()));
+        assert!(verify(&config, &forged).is_none());
+        let other_id = format!("{}.{}", uuid::Uuid::new_v4().simple(), signature);
+        assert!(verify(&config, &other_id).is_none());
+        assert!(verify(&config, "not-a-token").is_none());
+
+        let (token, _) = issue(&guest(&config)).unwrap();
+        let principal = verify(&config, &token).unwrap();
+        assert_eq!(principal.name(), Some("guest"));
+        // 設定から削除されたユーザーのセッションは使えない
+        assert!(verify(&AppConfig::default(), &token).is_none());
+    }
+
+    #[test]
+    fn expired_sessions_are_rejected() {
+        let _config_dir = TestConfigDir::new();
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let token = {
+            let mut store = STORE.write().unwrap();
+            store.sessions.insert(
+                id.clone(),
+                Session {
+                    user: None,
+                    expires_at: unix_now(),
+                },
+            );
+            store.token(&id)
+        };
+        assert!(verify(&AppConfig::default(), &token).is_none());
+    }
+
+    #[test]
+    fn tickets_can_be_redeemed_once() {
+        // `revoke_changed` がチケットを消すテストと同時に走らないように
+        let _config_dir = TestConfigDir::new();
+        let ticket = issue_ticket();
+        assert!(redeem_ticket(&ticket));
+        assert!(!redeem_ticket(&ticket));
+        assert!(!redeem_ticket("unknown"));
+
+        let expired = uuid::Uuid::new_v4().simple().to_string();
+        TICKETS
+            .lock()
+            .unwrap()
+            .insert(expired.clone(), Instant::now());
+        assert!(!redeem_ticket(&expired));
+    }
+
+    #[test]
+    fn changing_a_pin_revokes_only_its_sessions() {
+        let _config_dir = TestConfigDir::new();
+        let old = config_with_user("1234");
+        let (owner, _) = issue(&Principal::Owner).unwrap();
+        let (user, _) = issue(&guest(&old)).unwrap();
+        let device = add_device("Phone").unwrap();
+        let mut revoked = subscribe_revoked();
+
+        let new = config_with_user("5678");
+        revoke_changed(&old, &new).unwrap();
+        assert!(verify(&new, &owner).is_some());
+        assert!(verify(&new, &device).is_some());
+        assert!(verify(&new, &user).is_none());
+        assert!(revoked.try_recv().is_ok());
+
+        let ticket = issue_ticket();
+        let newer = AppConfig {
+            pin: "new-owner-pin".to_string(),
+            ..new.clone()
+        };
+        revoke_changed(&new, &newer).unwrap();
+        assert!(verify(&newer, &owner).is_none());
+        // ペアリングした端末は所有者として操作できるので承認を取り消す
+        assert!(verify(&newer, &device).is_none());
+        assert!(list_devices().is_empty());
+        // 全体のPINが変わると未使用のチケットも使えない
+        assert!(!redeem_ticket(&ticket));
+    }
+
+    #[test]
+    fn revoked_devices_can_no_longer_connect() {
+        let _config_dir = TestConfigDir::new();
+        let config = AppConfig::default();
+        let token = add_device("Phone").unwrap();
+        assert!(matches!(verify(&config, &token), Some(Principal::Owner)));
+        assert_eq!(device_name(&token).as_deref(), Some("Phone"));
+
+        let (id, _) = token.split_once('.').unwrap();
+        let mut revoked = subscribe_revoked();
+        revoke_device(id).unwrap();
+        assert!(verify(&config, &token).is_none());
+        assert!(device_name(&token).is_none());
+        assert!(revoked.try_recv().is_ok());
+        assert!(revoke_device(id).is_err());
+    }
+
+    #[tokio::test]
+    async fn device_access_time_is_saved_off_the_request() {
+        let _config_dir = TestConfigDir::new();
+        let token = add_device("Phone").unwrap();
+        let (id, _) = token.split_once('.').unwrap();
+        let set_last_seen = |last_seen| {
+            let mut store = STORE.write().unwrap();
+            let device = store.devices.iter_mut().find(|d| d.id == id).unwrap();
+            device.last_seen = last_seen;
+            save(&store).unwrap();
+        };
+        let last_seen = || {
+            list_devices()
+                .into_iter()
+                .find(|d| d.id == id)
+                .unwrap()
+                .last_seen
+        };
+
+        // 前回から間隔が空いていなければ更新しない
+        let recent = unix_now() - 1;
+        set_last_seen(recent);
+        verify(&AppConfig::default(), &token).unwrap();
+        assert_eq!(last_seen(), recent);
+
+        set_last_seen(0);
+        verify(&AppConfig::default(), &token).unwrap();
+        assert!(last_seen() >= unix_now() - 1);
+
+        let saved = || {
+            let content = fs::read_to_string(sessions_path()).unwrap();
+            let store: SessionStore = serde_json::from_str(&content).unwrap();
+            store.devices.iter().find(|d| d.id == id).unwrap().last_seen
+        };
+        for _ in 0..100 {
+            if saved() != 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(saved(), last_seen());
+        revoke_device(id).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sessions_file_is_only_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _config_dir = TestConfigDir::new();
+        issue(&Principal::Owner).unwrap();
+        let mode = fs::metadata(sessions_path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
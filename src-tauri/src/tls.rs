@@ -0,0 +1,212 @@
+use axum_server::tls_rustls::RustlsConfig;
+use rcgen::{CertificateParams, DnType, KeyPair};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::{restrict_to_owner, write_atomic, write_private, AppConfig};
+
+/// 証明書と秘密鍵を保存するディレクトリ（設定ファイルと同じディレクトリ内）
+const TLS_DIR_NAME: &str = "tls";
+const CERT_FILE_NAME: &str = "cert.pem";
+const KEY_FILE_NAME: &str = "key.pem";
+
+/// 証明書の有効期間（iOSは825日を超えるサーバー証明書を受け付けない）
+const CERT_VALIDITY_DAYS: i64 = 825;
+
+/// 有効期限がこの日数以内に迫った証明書は作り直す
+const RENEW_BEFORE_DAYS: i64 = 30;
+
+/// 待ち受け中のHTTPSの設定（証明書を作り直したときに差し替える）
+static ACTIVE: once_cell::sync::OnceCell<RustlsConfig> = once_cell::sync::OnceCell::new();
+
+/// 待ち受けに使っている証明書のフィンガープリント（`ACTIVE` と一緒に更新する）
+static ACTIVE_FINGERPRINT: Mutex<Option<String>> = Mutex::new(None);
+
+fn tls_dir() -> PathBuf {
+    AppConfig::config_dir().join(TLS_DIR_NAME)
+}
+
+/// 自己署名証明書と秘密鍵を生成して保存
+///
+/// スマホから接続に使うアドレスで警告が増えないよう、今のネットワークのアドレスを全て含める
+fn generate() -> Result<(), String> {
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    for iface in crate::network::list_interfaces() {
+        names.extend(iface.addresses.iter().map(|ip| ip.to_string()));
+    }
+
+    let mut params = CertificateParams::new(names).map_err(|e| e.to_string())?;
+    params.distinguished_name.push(DnType::CommonName, "TapKey");
+    let date_time = |date: chrono::NaiveDate| {
+        use chrono::Datelike;
+        rcgen::date_time_ymd(date.year(), date.month() as u8, date.day() as u8)
+    };
+    let today = chrono::Utc::now().date_naive();
+    params.not_before = date_time(today - chrono::Days::new(1));
+    params.not_after = date_time(today + chrono::Days::new(CERT_VALIDITY_DAYS as u64));
+
+    let key_pair = KeyPair::generate().map_err(|e| e.to_string())?;
+    let cert = params.self_signed(&key_pair).map_err(|e| e.to_string())?;
+
+    let dir = tls_dir();
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    // 秘密鍵は他のユーザーから読めないようにする
+    write_private(
+        &dir.join(KEY_FILE_NAME),
+        key_pair.serialize_pem().as_bytes(),
+    )
+    .map_err(|e| e.to_string())?;
+    write_atomic(&dir.join(CERT_FILE_NAME), cert.pem().as_bytes()).map_err(|e| e.to_string())?;
+    println!("Generated a new TLS certificate in {}", dir.display());
+    Ok(())
+}
+
+/// 保存済みの証明書がない、または有効期限が迫っているか
+fn needs_renewal() -> bool {
+    let dir = tls_dir();
+    if !dir.join(KEY_FILE_NAME).exists() {
+        return true;
+    }
+    let renew_after =
+        Duration::from_secs(((CERT_VALIDITY_DAYS - RENEW_BEFORE_DAYS) * 86400) as u64);
+    fs::metadata(dir.join(CERT_FILE_NAME))
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_none_or(|age| age >= renew_after)
+}
+
+/// 保存済みの証明書（なければ生成）を読み込む
+///
+/// 作り直すのは待ち受けを始めるときと `rotate` だけ（フィンガープリントが待ち受け中の証明書とずれないように）
+fn load() -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    if needs_renewal() {
+        generate()?;
+    }
+    read()
+}
+
+/// 保存済みの証明書を読み込む
+fn read() -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    let dir = tls_dir();
+    let certs = CertificateDer::pem_file_iter(dir.join(CERT_FILE_NAME))
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read the TLS certificate: {}", e))?;
+    if certs.is_empty() {
+        return Err("The TLS certificate file is empty".to_string());
+    }
+    // 以前のバージョンが誰でも読める権限で保存した秘密鍵も絞る
+    let key_path = dir.join(KEY_FILE_NAME);
+    if let Err(e) = restrict_to_owner(&key_path) {
+        eprintln!("Failed to restrict access to the TLS private key: {}", e);
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Failed to read the TLS private key: {}", e))?;
+    Ok((certs, key))
+}
+
+fn certificate_fingerprint(cert: &CertificateDer) -> String {
+    hex::encode_upper(Sha256::digest(cert))
+}
+
+/// 待ち受けに使う設定（`ACTIVE_FINGERPRINT` も更新する）
+fn server_config() -> Result<Arc<ServerConfig>, String> {
+    let (certs, key) = load()?;
+    let fingerprint = certificate_fingerprint(&certs[0]);
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid TLS certificate: {}", e))?;
+    // WebSocketをHTTP/2で張れないブラウザがあるのでHTTP/1.1だけにする
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    *ACTIVE_FINGERPRINT.lock().unwrap() = Some(fingerprint);
+    Ok(Arc::new(config))
+}
+
+/// HTTPSの待ち受けに使う設定（証明書がなければ生成する）
+pub fn listener_config() -> Result<RustlsConfig, String> {
+    ACTIVE
+        .get_or_try_init(|| server_config().map(RustlsConfig::from_config))
+        .cloned()
+}
+
+/// 証明書のSHA-256フィンガープリント（16進数の大文字、区切りなし）
+///
+/// 待ち受け中なら待ち受けに使っている証明書、まだなら保存済みの証明書（作り直さない）
+pub fn fingerprint() -> Result<String, String> {
+    if let Some(fingerprint) = ACTIVE_FINGERPRINT.lock().unwrap().clone() {
+        return Ok(fingerprint);
+    }
+    let (certs, _) = read().map_err(|e| {
+        format!(
+            "{} (the certificate is created when the HTTPS server starts)",
+            e
+        )
+    })?;
+    Ok(certificate_fingerprint(&certs[0]))
+}
+
+/// ブラウザの証明書ビューアと見比べやすい `AB:CD:...` 形式
+pub fn format_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| String::from_utf8_lossy(pair))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// 証明書を作り直し、待ち受け中なら新しい証明書に切り替えて新しいフィンガープリントを返す
+///
+/// 接続済みのスマホは次に接続したときに新しい証明書の確認を求められる
+pub fn rotate() -> Result<String, String> {
+    generate()?;
+    if let Some(active) = ACTIVE.get() {
+        active.reload_from_config(server_config()?);
+    }
+    fingerprint()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TestConfigDir;
+
+    #[test]
+    fn fingerprint_does_not_create_a_certificate() {
+        let _config_dir = TestConfigDir::new();
+        assert!(fingerprint().is_err());
+        assert!(!tls_dir().exists());
+
+        generate().unwrap();
+        let (certs, _) = read().unwrap();
+        assert_eq!(fingerprint().unwrap(), certificate_fingerprint(&certs[0]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_key_is_only_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _config_dir = TestConfigDir::new();
+        generate().unwrap();
+        let mode = fs::metadata(tls_dir().join(KEY_FILE_NAME))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
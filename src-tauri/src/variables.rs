@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::config::{write_atomic, AppConfig, VariableValue};
+use crate::server;
+
+/// 変数の現在値を保存するファイル（設定ファイルと同じディレクトリ）
+///
+/// 値が変わるたびに設定ファイルを書き換えるとバックアップや全クライアントの再読み込みが
+/// 走ってしまうので、初期値だけを設定ファイルに持ち、現在値はこちらに保存する
+const VARIABLES_FILE_NAME: &str = "variables.json";
+
+/// 保存済みの現在値（初回アクセス時に読み込む）
+static STORED: once_cell::sync::Lazy<RwLock<BTreeMap<String, VariableValue>>> =
+    once_cell::sync::Lazy::new(|| RwLock::new(load()));
+
+fn variables_path() -> PathBuf {
+    AppConfig::config_dir().join(VARIABLES_FILE_NAME)
+}
+
+fn load() -> BTreeMap<String, VariableValue> {
+    fs::read_to_string(variables_path())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save(values: &BTreeMap<String, VariableValue>) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(values).map_err(|e| e.to_string())?;
+    write_atomic(&variables_path(), content.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// 設定で宣言された全変数の現在値
+pub fn values() -> BTreeMap<String, VariableValue> {
+    let config = server::get_cached_config();
+    let stored = STORED.read().unwrap();
+    config
+        .variables
+        .into_iter()
+        .map(|(name, initial)| {
+            let value = stored.get(&name).cloned().unwrap_or(initial);
+            (name, value)
+        })
+        .collect()
+}
+
+/// 宣言済みの変数を更新して保存し、新しい値を返す
+fn update<F>(name: &str, f: F) -> Result<VariableValue, String>
+where
+    F: FnOnce(VariableValue, VariableValue) -> Result<VariableValue, String>,
+{
+    let config = server::get_cached_config();
+    let initial = config
+        .variables
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("Undefined variable: {}", name))?;
+
+    let mut stored = STORED.write().unwrap();
+    let current = stored.get(name).cloned().unwrap_or_else(|| initial.clone());
+    let value = f(current, initial)?;
+    stored.insert(name.to_string(), value.clone());
+    // 宣言が消えた変数の値は保存しない
+    stored.retain(|name, _| config.variables.contains_key(name));
+    save(&stored)?;
+    Ok(value)
+}
+
+pub fn set(name: &str, value: &VariableValue) -> Result<VariableValue, String> {
+    update(name, |_, _| Ok(value.clone()))
+}
+
+pub fn increment(name: &str, by: i64) -> Result<VariableValue, String> {
+    update(name, |current, _| match current {
+        VariableValue::Number(n) => Ok(VariableValue::Number(n.saturating_add(by))),
+        VariableValue::Text(_) => Err(format!("Variable {} is not a number", name)),
+    })
+}
+
+pub fn reset(name: &str) -> Result<VariableValue, String> {
+    update(name, |_, initial| Ok(initial))
+}
+
+/// 全変数を初期値に戻す
+pub fn reset_all() -> Result<(), String> {
+    let mut stored = STORED.write().unwrap();
+    stored.clear();
+    save(&stored)
+}
+
+/// `{変数名}` を変数の値に置き換える（宣言されていない名前はそのまま残す）
+pub fn interpolate(text: &str) -> String {
+    let values = values();
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .and_then(|end| Some((values.get(&after[..end])?, end)))
+        {
+            Some((value, end)) => {
+                result.push_str(&value.to_string());
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
@@ -0,0 +1,64 @@
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode, DebounceEventResult};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::config::{AppConfig, ConfigLoadError};
+use crate::server;
+
+/// 変更イベントをまとめる待ち時間（エディタの連続書き込みをまとめる）
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// 設定ファイルの外部変更を監視し、反映結果を `on_change` に渡す
+///
+/// 保存時はリネームでファイルが置き換わり、形式の変換で拡張子も変わるので、
+/// ファイルではなくディレクトリを監視する
+pub fn start<F>(on_change: F)
+where
+    F: Fn(Result<(), ConfigLoadError>) + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+        let mut debouncer = match new_debouncer(DEBOUNCE, tx) {
+            Ok(debouncer) => debouncer,
+            Err(e) => {
+                eprintln!("Failed to create config watcher: {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = debouncer
+            .watcher()
+            .watch(&AppConfig::config_dir(), RecursiveMode::NonRecursive)
+        {
+            eprintln!("Failed to watch config directory: {}", e);
+            return;
+        }
+
+        for result in rx {
+            let Ok(events) = result else {
+                continue;
+            };
+            // 形式の変換でファイル名が変わることがあるので毎回パスを求める
+            let config_path = AppConfig::config_path();
+            if !events
+                .iter()
+                .any(|event| event.path.file_name() == config_path.file_name())
+            {
+                continue;
+            }
+            // 削除された場合は次に書き込まれるまで現在の設定を使い続ける
+            if !config_path.exists() {
+                continue;
+            }
+
+            match server::apply_external_change() {
+                Ok(true) => on_change(Ok(())),
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Ignoring invalid config change: {}", e);
+                    on_change(Err(e));
+                }
+            }
+        }
+    });
+}
@@ -2,12 +2,19 @@
     'use strict';
 
     // State
-    let currentPin = '';
+    let sessionToken = localStorage.getItem('tapkey_token') || ''; // /api/auth で発行されたトークン
     let config = null;
+    let currentPageId = 'main'; // 表示中のページ
+    let currentTheme = {}; // 表示中のページに適用しているテーマ
+    let dynamicLabels = {}; // ボタンID -> サーバーで評価された動的ラベル
     let repeatIntervals = {}; // ボタンID -> intervalId のマップ
     let repeatTimeouts = {}; // 長押し開始のタイムアウト
     let ws = null; // WebSocket接続
     let wsReconnectTimer = null;
+    let wsHeartbeatTimer = null;
+    let awaitingPong = false; // Pingを送ってまだPongが届いていない
+    let executeSeq = 0; // WebSocketで送ったExecuteの番号
+    let pendingExecutes = {}; // Executeの番号 -> 結果待ち { resolve, timer }
 
     // 色の明るさを調整するヘルパー関数
     function adjustBrightness(hex, percent) {
@@ -53,8 +60,11 @@
             `;
         }
 
-        // 暗い色のボタンは白文字
-        if (isColorDark(color)) {
+        // 暗い色のボタンは白文字（テーマで指定があればその色）
+        if (currentTheme.colored_label_color) {
+            button.style.color = currentTheme.colored_label_color;
+            button.style.textShadow = 'none';
+        } else if (isColorDark(color)) {
             button.style.color = 'white';
             button.style.textShadow = '0 1px 2px rgba(0, 0, 0, 0.3)';
         } else {
@@ -69,17 +79,32 @@
     const pinInput = document.getElementById('pin-input');
     const authButton = document.getElementById('auth-button');
     const authError = document.getElementById('auth-error');
+    const pairButton = document.getElementById('pair-button');
+    const pairStatus = document.getElementById('pair-status');
+    const certFingerprint = document.getElementById('cert-fingerprint');
     const buttonGrid = document.getElementById('button-grid');
     const statusText = document.getElementById('status-text');
+    const pageBar = document.getElementById('page-bar');
+
+    // 画面の向き（回転したらボタンを描画し直す）
+    const landscapeQuery = window.matchMedia('(orientation: landscape)');
+    landscapeQuery.addEventListener('change', () => renderButtons());
 
-    // API calls
+    // API calls（セッショントークンがあれば Authorization ヘッダーで送る）
     async function apiCall(endpoint, data) {
+        const headers = { 'Content-Type': 'application/json' };
+        if (sessionToken) {
+            headers['Authorization'] = `Bearer ${sessionToken}`;
+        }
         try {
             const response = await fetch(endpoint, {
                 method: 'POST',
-                headers: { 'Content-Type': 'application/json' },
-                body: JSON.stringify(data)
+                headers,
+                body: JSON.stringify(data || {})
             });
+            if (response.status === 401 && endpoint !== '/api/auth') {
+                sessionExpired();
+            }
             return await response.json();
         } catch (error) {
             console.error('API Error:', error);
@@ -87,17 +112,39 @@
         }
     }
 
+    // PIN（またはQRコードのチケット）でセッショントークンを発行してもらう
+    async function login(credentials) {
+        const result = await apiCall('/api/auth', credentials);
+        if (result.success && result.token) {
+            sessionToken = result.token;
+            localStorage.setItem('tapkey_token', sessionToken);
+        }
+        return result;
+    }
+
+    // セッションが期限切れ・失効した場合はPIN入力に戻る
+    function sessionExpired() {
+        sessionToken = '';
+        localStorage.removeItem('tapkey_token');
+        config = null;
+        if (ws) {
+            ws.close();
+        }
+        mainScreen.classList.add('hidden');
+        authScreen.classList.remove('hidden');
+        authButton.disabled = false;
+        authError.textContent = 'Session expired. Please enter the PIN again.';
+    }
+
     // 認証
     async function authenticate() {
         const pin = pinInput.value;
         authError.textContent = '';
         authButton.disabled = true;
 
-        const result = await apiCall('/api/auth', { pin });
+        const result = await login({ pin });
 
-        if (result.success) {
-            currentPin = pin;
-            await loadConfig();
+        if (result.success && await loadConfig()) {
             showMainScreen();
         } else {
             authError.textContent = result.message || 'Authentication failed';
@@ -105,17 +152,67 @@
         }
     }
 
-    // 設定を読み込み
+    // PINの代わりにPCでの承認を求める（承認されると期限なしのトークンを受け取る）
+    async function requestPairing() {
+        const name = prompt('Name for this device', localStorage.getItem('tapkey_device_name') || 'My phone');
+        if (!name) {
+            return;
+        }
+        localStorage.setItem('tapkey_device_name', name);
+        authError.textContent = '';
+
+        const result = await apiCall('/api/pair', { name });
+        if (!result.success) {
+            authError.textContent = result.message || 'Pairing failed';
+            return;
+        }
+        pairButton.disabled = true;
+        pairStatus.textContent = `Approve "${name}" on your computer. Code: ${result.code}`;
+        pairStatus.classList.remove('hidden');
+        waitForPairing(result.request_id);
+    }
+
+    // 承認されるまで問い合わせを続ける
+    async function waitForPairing(requestId) {
+        const result = await apiCall('/api/pair/status', { request_id: requestId });
+        // 承認待ち、または一時的に繋がらない
+        if (result.status === 'pending' || !result.status) {
+            setTimeout(() => waitForPairing(requestId), 2000);
+            return;
+        }
+
+        pairStatus.classList.add('hidden');
+        pairButton.disabled = false;
+        if (result.status === 'approved') {
+            sessionToken = result.token;
+            localStorage.setItem('tapkey_token', sessionToken);
+            if (await loadConfig()) {
+                showMainScreen();
+            }
+        } else if (result.status === 'denied') {
+            authError.textContent = 'Pairing was denied';
+        } else {
+            authError.textContent = 'Pairing request expired';
+        }
+    }
+
+    // 接続中の表示（ユーザーで認証した場合は名前も表示）
+    function connectedText() {
+        return config && config.user ? `Connected (${config.user})` : 'Connected';
+    }
+
+    // 設定を読み込み（読み込めたら true）
     async function loadConfig() {
-        const result = await apiCall('/api/config', { pin: currentPin });
+        const result = await apiCall('/api/config');
 
         if (result.grid) {
             config = result;
             renderButtons();
-            statusText.textContent = 'Connected';
-        } else {
-            statusText.textContent = 'Failed to load config';
+            statusText.textContent = connectedText();
+            return true;
         }
+        statusText.textContent = 'Failed to load config';
+        return false;
     }
 
     // WebSocket接続
@@ -127,8 +224,14 @@
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
         const wsUrl = `${protocol}//${window.location.host}/ws`;
 
+        // セッションが無いまま再接続しない
+        if (!sessionToken) {
+            return;
+        }
+
         try {
-            ws = new WebSocket(wsUrl);
+            // ブラウザのWebSocketはヘッダーを付けられないのでサブプロトコルでトークンを渡す
+            ws = new WebSocket(wsUrl, ['tapkey', sessionToken]);
 
             ws.onopen = () => {
                 console.log('WebSocket connected');
@@ -136,23 +239,48 @@
                     clearTimeout(wsReconnectTimer);
                     wsReconnectTimer = null;
                 }
+                // 設定の差分を購読（切断中の変更は手元のリビジョンからの差分で届く）
+                ws.send(JSON.stringify({
+                    type: 'Subscribe',
+                    data: { revision: config ? config.revision : null }
+                }));
+                startHeartbeat();
             };
 
             ws.onmessage = async (event) => {
                 try {
                     const msg = JSON.parse(event.data);
-                    if (msg.type === 'ConfigUpdated') {
+                    if (msg.type === 'Pong') {
+                        awaitingPong = false;
+                    } else if (msg.type === 'ExecuteAck') {
+                        settleExecute(msg.data.id, msg.data);
+                        if (msg.data.code === 'invalid_session') {
+                            sessionExpired();
+                        }
+                    } else if (msg.type === 'ConfigUpdated') {
                         console.log('Config updated, reloading...');
                         await loadConfig();
+                    } else if (msg.type === 'ConfigPatch') {
+                        await applyConfigPatch(msg.data);
+                    } else if (msg.type === 'LabelsUpdated') {
+                        Object.assign(dynamicLabels, msg.data);
+                        Object.entries(msg.data).forEach(([id, label]) => setButtonLabel(id, label));
                     }
                 } catch (e) {
                     console.error('WebSocket message parse error:', e);
                 }
             };
 
-            ws.onclose = () => {
+            ws.onclose = (event) => {
                 console.log('WebSocket disconnected');
                 ws = null;
+                stopHeartbeat();
+                failPendingExecutes();
+                // セッションが無効・期限切れ、またはペアリングした端末が取り消された
+                if (event.code === 4001) {
+                    sessionExpired();
+                    return;
+                }
                 // 再接続を試みる
                 scheduleReconnect();
             };
@@ -166,6 +294,38 @@
         }
     }
 
+    // 応答のない接続（スリープからの復帰後など）を見つけて繋ぎ直す
+    function startHeartbeat() {
+        stopHeartbeat();
+        awaitingPong = false;
+        wsHeartbeatTimer = setInterval(() => {
+            if (!ws) {
+                return;
+            }
+            if (awaitingPong) {
+                console.log('WebSocket not responding, reconnecting...');
+                // 閉じ終わるのを待たずに新しい接続を張る
+                const stale = ws;
+                ws = null;
+                stale.onclose = null;
+                stale.close();
+                stopHeartbeat();
+                failPendingExecutes();
+                scheduleReconnect();
+                return;
+            }
+            awaitingPong = true;
+            ws.send(JSON.stringify({ type: 'Ping' }));
+        }, 25000);
+    }
+
+    function stopHeartbeat() {
+        if (wsHeartbeatTimer) {
+            clearInterval(wsHeartbeatTimer);
+            wsHeartbeatTimer = null;
+        }
+    }
+
     // 再接続をスケジュール
     function scheduleReconnect() {
         if (wsReconnectTimer) return;
@@ -175,49 +335,270 @@
         }, 3000); // 3秒後に再接続
     }
 
+    // 設定の差分を適用（手元のリビジョンと合わなければ読み直す）
+    async function applyConfigPatch(patch) {
+        if (!config || config.revision !== patch.base_revision) {
+            console.log('Config revision mismatch, reloading...');
+            await loadConfig();
+            return;
+        }
+
+        const mainWasEmpty = config.buttons.length === 0;
+        let needsRender = false;
+        patch.pages.forEach(diff => {
+            // メインページは設定の直下に持っている
+            const page = diff.page_id === 'main' ? config : config.pages.find(p => p.id === diff.page_id);
+            if (!page) return;
+
+            const isCurrent = diff.page_id === currentPage().id;
+            if (diff.grid !== undefined) {
+                page.grid = diff.grid;
+                needsRender = needsRender || isCurrent;
+            }
+            if (diff.landscape_grid !== undefined) {
+                page.landscape_grid = diff.landscape_grid;
+                needsRender = needsRender || isCurrent;
+            }
+
+            const removed = diff.removed || [];
+            const changed = diff.changed || [];
+            const added = diff.added || [];
+            page.buttons = page.buttons
+                .filter(btn => !removed.includes(btn.id))
+                .map(btn => changed.find(c => c.id === btn.id) || btn)
+                .concat(added);
+
+            if (isCurrent && !needsRender) {
+                patchButtons(removed, changed, added);
+            }
+        });
+        config.revision = patch.revision;
+
+        // メインページの表示・非表示が変わるとページ切り替えバーも変わる
+        if (needsRender || mainWasEmpty !== (config.buttons.length === 0)) {
+            renderButtons();
+        }
+    }
+
+    // 表示中のボタンだけを差し替え
+    function patchButtons(removed, changed, added) {
+        const landscape = landscapeQuery.matches && !!currentPage().landscape_grid;
+        const findElement = id => buttonGrid.querySelector(`[data-id="${CSS.escape(id)}"]`);
+
+        removed.forEach(id => {
+            stopRepeat(id);
+            const element = findElement(id);
+            if (element) element.remove();
+        });
+        changed.forEach(btn => {
+            stopRepeat(btn.id);
+            const element = findElement(btn.id);
+            if (element) {
+                element.replaceWith(createButton(btn, landscape));
+            } else {
+                buttonGrid.appendChild(createButton(btn, landscape));
+            }
+        });
+        added.forEach(btn => buttonGrid.appendChild(createButton(btn, landscape)));
+    }
+
+    // メインページを先頭にした全ページ
+    function allPages() {
+        const mainPage = {
+            id: 'main',
+            name: 'Main',
+            grid: config.grid,
+            landscape_grid: config.landscape_grid,
+            buttons: config.buttons
+        };
+        const pages = config.pages || [];
+        // 使えるボタンが無いメインページは表示しない（ユーザーごとの制限）
+        if (config.buttons.length === 0 && pages.length > 0) {
+            return pages;
+        }
+        return [mainPage].concat(pages);
+    }
+
+    // 表示中のページ（削除されていたらメインページ）
+    function currentPage() {
+        const pages = allPages();
+        return pages.find(p => p.id === currentPageId) || pages[0];
+    }
+
+    // 全体のテーマにページの上書きを重ねる
+    function pageTheme(page) {
+        const theme = Object.assign({}, config.theme || {});
+        Object.entries(page.theme || {}).forEach(([key, value]) => {
+            if (value !== null && value !== undefined) {
+                theme[key] = value;
+            }
+        });
+        return theme;
+    }
+
+    // テーマをCSS変数として適用（未指定の項目は style.css の標準値）
+    function applyTheme(theme) {
+        currentTheme = theme;
+        document.body.classList.toggle('theme-light', theme.mode === 'light');
+
+        const px = value => (value === null || value === undefined ? null : `${value}px`);
+        const vars = {
+            '--bg-color': theme.background,
+            '--button-font-size': px(theme.font_size),
+            '--grid-gap': px(theme.gap),
+            '--button-radius': px(theme.corner_radius),
+            '--label-color': theme.label_color
+        };
+        Object.entries(vars).forEach(([name, value]) => {
+            if (value) {
+                document.body.style.setProperty(name, value);
+            } else {
+                document.body.style.removeProperty(name);
+            }
+        });
+    }
+
+    // ボタン押下時の振動
+    function hapticFeedback() {
+        const pattern = currentTheme.haptic_pattern;
+        if (pattern && pattern.length > 0 && navigator.vibrate) {
+            navigator.vibrate(pattern);
+        }
+    }
+
+    // ページ切り替えバーを描画（追加ページがある場合のみ表示）
+    function renderPageBar() {
+        const pages = allPages();
+        pageBar.innerHTML = '';
+        pageBar.classList.toggle('hidden', pages.length < 2);
+
+        pages.forEach(page => {
+            const tab = document.createElement('button');
+            tab.className = 'page-tab';
+            tab.textContent = page.name;
+            if (page.id === currentPage().id) {
+                tab.classList.add('active');
+            }
+            tab.addEventListener('click', () => {
+                currentPageId = page.id;
+                renderButtons();
+            });
+            pageBar.appendChild(tab);
+        });
+    }
+
     // ボタンを描画
     function renderButtons() {
         if (!config) return;
 
+        const page = currentPage();
+        applyTheme(pageTheme(page));
+        renderPageBar();
         buttonGrid.innerHTML = '';
 
+        // 横向きのレイアウトがあれば画面の向きに合わせて切り替え
+        const landscape = landscapeQuery.matches && !!page.landscape_grid;
+        const grid = landscape ? page.landscape_grid : page.grid;
+
         // グリッドスタイルを設定
-        buttonGrid.style.gridTemplateColumns = `repeat(${config.grid.columns}, 1fr)`;
-        buttonGrid.style.gridTemplateRows = `repeat(${config.grid.rows}, 1fr)`;
+        buttonGrid.style.gridTemplateColumns = `repeat(${grid.columns}, 1fr)`;
+        buttonGrid.style.gridTemplateRows = `repeat(${grid.rows}, 1fr)`;
 
         // ボタンを作成
-        config.buttons.forEach(btn => {
-            const button = document.createElement('button');
-            button.className = 'grid-button';
-            button.textContent = btn.label;
-            button.dataset.id = btn.id;
-
-            // グリッド位置を設定
-            button.style.gridColumn = `${btn.position.x + 1} / span ${btn.position.width}`;
-            button.style.gridRow = `${btn.position.y + 1} / span ${btn.position.height}`;
-
-            // 色を設定（立体感のあるスタイル）
-            if (btn.color) {
-                const color = btn.color;
-                button.dataset.color = color;
-                applyButtonStyle(button, color, false);
-            }
+        page.buttons.forEach(btn => buttonGrid.appendChild(createButton(btn, landscape)));
+    }
 
-            // リピート設定を保存
-            if (btn.repeat && btn.repeat.enabled) {
-                button.dataset.repeatEnabled = 'true';
-                button.dataset.repeatInterval = btn.repeat.interval_ms || 100;
-            }
+    // ボタン要素を作成
+    function createButton(btn, landscape) {
+        const button = document.createElement('button');
+        button.className = 'grid-button';
+        const icon = createIcon(btn.icon);
+        if (icon) {
+            button.classList.add('has-icon');
+            button.appendChild(icon);
+            const label = document.createElement('span');
+            label.className = 'button-label';
+            label.textContent = labelFor(btn);
+            button.appendChild(label);
+        } else {
+            button.textContent = labelFor(btn);
+        }
+        button.dataset.id = btn.id;
 
-            // イベント
-            button.addEventListener('touchstart', handleButtonPress, { passive: true });
-            button.addEventListener('touchend', handleButtonRelease);
-            button.addEventListener('mousedown', handleButtonPress);
-            button.addEventListener('mouseup', handleButtonRelease);
-            button.addEventListener('mouseleave', handleButtonRelease);
+        // グリッド位置を設定
+        const position = (landscape && btn.landscape_position) || btn.position;
+        button.style.gridColumn = `${position.x + 1} / span ${position.width}`;
+        button.style.gridRow = `${position.y + 1} / span ${position.height}`;
 
-            buttonGrid.appendChild(button);
-        });
+        // 色を設定（立体感のあるスタイル）
+        if (btn.color) {
+            const color = btn.color;
+            button.dataset.color = color;
+            applyButtonStyle(button, color, false);
+        }
+
+        // リピート設定を保存
+        if (btn.repeat && btn.repeat.enabled) {
+            button.dataset.repeatEnabled = 'true';
+            button.dataset.repeatInterval = btn.repeat.interval_ms || 100;
+        }
+
+        // イベント
+        button.addEventListener('touchstart', handleButtonPress, { passive: true });
+        button.addEventListener('touchend', handleButtonRelease);
+        button.addEventListener('mousedown', handleButtonPress);
+        button.addEventListener('mouseup', handleButtonRelease);
+        button.addEventListener('mouseleave', handleButtonRelease);
+
+        return button;
+    }
+
+    // ボタンに表示するラベル（動的ラベルが届いていればそちら）
+    function labelFor(btn) {
+        if (btn.dynamic_label && dynamicLabels[btn.id] !== undefined) {
+            return dynamicLabels[btn.id];
+        }
+        return btn.label;
+    }
+
+    // 表示中のボタンのラベルを書き換え
+    function setButtonLabel(buttonId, label) {
+        const button = buttonGrid.querySelector(`[data-id="${CSS.escape(buttonId)}"]`);
+        if (!button) return;
+        const target = button.querySelector('.button-label') || button;
+        target.textContent = label;
+    }
+
+    // アイコン要素を作成
+    function createIcon(icon) {
+        if (!icon) return null;
+        switch (icon.type) {
+            case 'asset': {
+                const img = document.createElement('img');
+                img.className = 'button-icon';
+                img.src = `/assets/${encodeURIComponent(icon.name)}`;
+                img.alt = '';
+                return img;
+            }
+            case 'svg': {
+                const img = document.createElement('img');
+                img.className = 'button-icon';
+                img.src = `data:image/svg+xml,${encodeURIComponent(icon.svg)}`;
+                img.alt = '';
+                return img;
+            }
+            case 'builtin': {
+                // 文字色で塗るためマスクとして使う
+                const span = document.createElement('span');
+                span.className = 'button-icon builtin';
+                const url = `url(/icons/${encodeURIComponent(icon.name)}.svg)`;
+                span.style.webkitMaskImage = url;
+                span.style.maskImage = url;
+                return span;
+            }
+            default:
+                return null;
+        }
     }
 
     // ボタン押下処理
@@ -226,6 +607,7 @@
         const buttonId = button.dataset.id;
 
         button.classList.add('pressed');
+        hapticFeedback();
         const color = button.dataset.color;
         if (color) {
             applyButtonStyle(button, color, true);
@@ -248,6 +630,18 @@
         }
     }
 
+    // リピートのタイムアウトとインターバルをクリア
+    function stopRepeat(buttonId) {
+        if (repeatTimeouts[buttonId]) {
+            clearTimeout(repeatTimeouts[buttonId]);
+            delete repeatTimeouts[buttonId];
+        }
+        if (repeatIntervals[buttonId]) {
+            clearInterval(repeatIntervals[buttonId]);
+            delete repeatIntervals[buttonId];
+        }
+    }
+
     async function handleButtonRelease(e) {
         const button = e.currentTarget;
         const buttonId = button.dataset.id;
@@ -260,15 +654,7 @@
             applyButtonStyle(button, color, false);
         }
 
-        // リピートのタイムアウトとインターバルをクリア
-        if (repeatTimeouts[buttonId]) {
-            clearTimeout(repeatTimeouts[buttonId]);
-            delete repeatTimeouts[buttonId];
-        }
-        if (repeatIntervals[buttonId]) {
-            clearInterval(repeatIntervals[buttonId]);
-            delete repeatIntervals[buttonId];
-        }
+        stopRepeat(buttonId);
 
         if (e.type === 'mouseleave') return;
 
@@ -285,12 +671,43 @@
         await executeAction(buttonId, button);
     }
 
+    // アクションを接続中のWebSocketで実行（接続していなければHTTPで送る）
+    function sendAction(buttonId) {
+        if (!ws || ws.readyState !== WebSocket.OPEN) {
+            return apiCall('/api/action', { button_id: buttonId });
+        }
+        const id = ++executeSeq;
+        return new Promise((resolve) => {
+            // 結果が届かなくても実行済みかもしれないので、HTTPで送り直さない
+            const timer = setTimeout(() => {
+                settleExecute(id, { success: false, message: 'No response' });
+            }, 5000);
+            pendingExecutes[id] = { resolve, timer };
+            ws.send(JSON.stringify({
+                type: 'Execute',
+                data: { id, button_id: buttonId }
+            }));
+        });
+    }
+
+    function settleExecute(id, result) {
+        const pending = pendingExecutes[id];
+        if (!pending) return;
+        clearTimeout(pending.timer);
+        delete pendingExecutes[id];
+        pending.resolve(result);
+    }
+
+    // 接続が切れたら結果待ちのExecuteを失敗にする
+    function failPendingExecutes() {
+        Object.keys(pendingExecutes).forEach((id) => {
+            settleExecute(id, { success: false, message: 'Connection lost' });
+        });
+    }
+
     // アクション実行
     async function executeAction(buttonId, buttonElement) {
-        const result = await apiCall('/api/action', {
-            button_id: buttonId,
-            pin: currentPin
-        });
+        const result = await sendAction(buttonId);
 
         if (result.success) {
             buttonElement.classList.add('success');
@@ -300,7 +717,7 @@
         } else {
             statusText.textContent = result.message || 'エラー';
             setTimeout(() => {
-                statusText.textContent = 'Connected';
+                statusText.textContent = connectedText();
             }, 2000);
         }
     }
@@ -319,25 +736,59 @@
         return params.get('pin') || '';
     }
 
-    // ローカルストレージからPINを復元
-    function restorePin() {
-        // URLパラメータを優先
-        const urlPin = getPinFromUrl();
-        if (urlPin) {
-            return urlPin;
+    // QRコードに埋め込まれた使い捨てのログインチケット
+    function getTicketFromUrl() {
+        const params = new URLSearchParams(window.location.search);
+        return params.get('ticket') || '';
+    }
+
+    // QRコードに埋め込まれたHTTPS証明書のフィンガープリント（前回のものは保存しておく）
+    function getFingerprint() {
+        const params = new URLSearchParams(window.location.search);
+        const fingerprint = params.get('fp');
+        if (fingerprint) {
+            localStorage.setItem('tapkey_fingerprint', fingerprint);
+            return fingerprint;
         }
-        return localStorage.getItem('tapkey_pin') || '';
+        return localStorage.getItem('tapkey_fingerprint') || '';
     }
 
-    // PINを保存
-    function savePin(pin) {
-        localStorage.setItem('tapkey_pin', pin);
+    // ブラウザの証明書の表示と見比べられるよう、フィンガープリントを AB:CD:... の形式で表示
+    function showFingerprint() {
+        const fingerprint = getFingerprint();
+        if (window.location.protocol !== 'https:' || !fingerprint) {
+            return;
+        }
+        certFingerprint.textContent = 'Certificate SHA-256: ' + fingerprint.match(/.{1,2}/g).join(':');
+        certFingerprint.classList.remove('hidden');
+    }
+
+    // 保存済みのトークンかPINで自動認証
+    // （QRコードのチケット・URLパラメータのPIN・旧バージョンで保存したPIN・PINなしの順）
+    async function autoLogin() {
+        const ticket = getTicketFromUrl();
+        const urlPin = getPinFromUrl();
+        if (!ticket && !urlPin && sessionToken && await loadConfig()) {
+            return true;
+        }
+
+        const legacyPin = localStorage.getItem('tapkey_pin');
+        localStorage.removeItem('tapkey_pin');
+        const result = ticket
+            ? await login({ ticket })
+            : await login({ pin: urlPin || legacyPin || '' });
+        // PINやチケットをアドレスバーや履歴に残さない
+        if (ticket || urlPin) {
+            history.replaceState(null, '', window.location.pathname);
+        }
+        // チケットが期限切れでも、ログイン済みならそのセッションを使い続ける
+        return (result.success || !!sessionToken) && await loadConfig();
     }
 
     // 初期化
     function init() {
-        const savedPin = restorePin();
-        pinInput.value = savedPin;
+        pinInput.value = getPinFromUrl();
+        showFingerprint();
 
         // リロードボタン
         const reloadBtn = document.getElementById('reload-btn');
@@ -348,31 +799,19 @@
         }
 
         // 認証ボタン
-        authButton.addEventListener('click', async () => {
-            await authenticate();
-            if (currentPin !== null) {
-                savePin(currentPin);
-            }
-        });
+        authButton.addEventListener('click', authenticate);
+        pairButton.addEventListener('click', requestPairing);
 
         // Enterキーで認証
         pinInput.addEventListener('keypress', async (e) => {
             if (e.key === 'Enter') {
                 await authenticate();
-                if (currentPin !== null) {
-                    savePin(currentPin);
-                }
             }
         });
 
-        // URLパラメータまたは保存済みPINで自動認証を試みる
+        // 保存済みのトークンまたはPINで自動認証を試みる
         setTimeout(async () => {
-            const pinToTry = savedPin || '';
-            const result = await apiCall('/api/auth', { pin: pinToTry });
-            if (result.success) {
-                currentPin = pinToTry;
-                savePin(currentPin);
-                await loadConfig();
+            if (await autoLogin()) {
                 showMainScreen();
             }
         }, 100);
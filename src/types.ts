@@ -20,26 +20,256 @@ export interface TextAndEnterAction {
   text: string;
 }
 
-export type ButtonAction = ShortcutAction | TextAndEnterAction;
+export type VariableValue = number | string;
+
+export interface SetVariableAction {
+  type: 'set_variable';
+  name: string;
+  value: VariableValue;
+}
+
+export interface IncrementVariableAction {
+  type: 'increment_variable';
+  name: string;
+  by: number;
+}
+
+export interface ResetVariableAction {
+  type: 'reset_variable';
+  name: string;
+}
+
+export type ButtonAction =
+  | ShortcutAction
+  | TextAndEnterAction
+  | SetVariableAction
+  | IncrementVariableAction
+  | ResetVariableAction;
 
 export interface RepeatConfig {
   enabled: boolean;
   interval_ms: number;
 }
 
+export interface AssetIcon {
+  type: 'asset';
+  name: string;
+}
+
+export interface SvgIcon {
+  type: 'svg';
+  svg: string;
+}
+
+export interface BuiltinIcon {
+  type: 'builtin';
+  name: string;
+}
+
+export type ButtonIcon = AssetIcon | SvgIcon | BuiltinIcon;
+
 export interface ButtonConfig {
   id: string;
   label: string;
   position: ButtonPosition;
+  landscape_position?: ButtonPosition;
   action: ButtonAction;
   color?: string;
   repeat?: RepeatConfig;
+  icon?: ButtonIcon;
+  dynamic_label?: DynamicLabel;
+}
+
+export interface ClockSource {
+  type: 'clock';
+  format: string;
+}
+
+export interface CounterSource {
+  type: 'counter';
+  start: number;
+  step: number;
+}
+
+export interface GitBranchSource {
+  type: 'git_branch';
+  path: string;
+}
+
+export interface CommandSource {
+  type: 'command';
+  command: string;
+}
+
+export type LabelSource = ClockSource | CounterSource | GitBranchSource | CommandSource;
+
+export interface DynamicLabel {
+  source: LabelSource;
+  template: string;
+  interval_ms: number;
+}
+
+export interface PageConfig {
+  id: string;
+  name: string;
+  grid: GridConfig;
+  landscape_grid?: GridConfig;
+  buttons: ButtonConfig[];
+  theme?: ThemeConfig;
+}
+
+export type ThemeMode = 'dark' | 'light';
+
+export interface ThemeConfig {
+  mode?: ThemeMode;
+  background?: string;
+  font_size?: number;
+  gap?: number;
+  corner_radius?: number;
+  label_color?: string;
+  colored_label_color?: string;
+  haptic_pattern?: number[];
+}
+
+export interface UserConfig {
+  name: string;
+  pin: string;
+  pages?: string[];
+  buttons?: string[];
+}
+
+export interface AuthLimitConfig {
+  max_attempts_per_ip: number;
+  max_attempts_global: number;
+  global_window_secs: number;
+  backoff_base_ms: number;
+  lockout_secs: number;
+}
+
+export interface ListenAll {
+  type: 'all';
+  ipv6: boolean;
+}
+
+export interface ListenLocalhost {
+  type: 'localhost';
+}
+
+export interface ListenInterfaces {
+  type: 'interfaces';
+  names: string[];
+  ipv6: boolean;
+}
+
+export interface ListenAddresses {
+  type: 'addresses';
+  addresses: string[];
+}
+
+export type ListenConfig = ListenAll | ListenLocalhost | ListenInterfaces | ListenAddresses;
+
+export interface NetworkInterface {
+  name: string;
+  addresses: string[];
+}
+
+export interface PairingRequest {
+  id: string;
+  name: string;
+  address: string;
+  code: string;
+}
+
+export interface TrustedDevice {
+  id: string;
+  name: string;
+  paired_at: number;
+  last_seen: number;
+}
+
+export interface AuditEntry {
+  timestamp: number; // UNIX時間のミリ秒
+  device: string | null;
+  user: string | null;
+  session: string;
+  ip: string;
+  button_id: string;
+  transport: 'http' | 'websocket';
+  success: boolean;
+  code?: string;
+  error?: string;
+  latency_ms: number;
+}
+
+export interface AuditQuery {
+  device?: string | null;
+  button_id?: string | null;
+  ip?: string | null;
+  success?: boolean | null;
+  since?: number | null;
+  until?: number | null;
+  limit?: number | null;
 }
 
 export interface AppConfig {
   port: number;
+  listen: ListenConfig;
+  https: boolean;
+  allowed_origins: string[];
   pin: string;
+  users: UserConfig[];
+  auth_limits: AuthLimitConfig;
   auto_start: boolean;
   grid: GridConfig;
+  landscape_grid?: GridConfig;
   buttons: ButtonConfig[];
+  pages: PageConfig[];
+  theme: ThemeConfig;
+  variables: Record<string, VariableValue>;
+}
+
+export interface ConfigLoadError {
+  path: string;
+  message: string;
+  line: number | null;
+  column: number | null;
+  backup_path: string | null;
+  using_defaults: boolean;
+}
+
+export interface ConfigBackup {
+  name: string;
+  created_at: number;
+  size: number;
+}
+
+export interface LayoutBundle {
+  format: string;
+  version: number;
+  name: string;
+  exported_at: number;
+  pages: PageConfig[];
+  assets?: Record<string, string>;
+  variables?: Record<string, VariableValue>;
 }
+
+export type ConflictStrategy = 'rename' | 'replace' | 'skip';
+
+export type PageChange =
+  | { change: 'added'; id: string; name: string; buttons: number }
+  | { change: 'replaced'; id: string; name: string; buttons_added: number; buttons_removed: number }
+  | { change: 'renamed'; from: string; to: string; name: string; buttons: number }
+  | { change: 'skipped'; id: string; name: string };
+
+export interface ImportPreview {
+  pages: PageChange[];
+  renamed_buttons: [string, string][];
+  removed_commands: string[];
+}
+
+export type ConfigFormat = 'json' | 'jsonc' | 'toml' | 'yaml';
+
+export type ConfigLocation =
+  | { mode: 'default' }
+  | { mode: 'file'; path: string }
+  | { mode: 'portable'; path: string };